@@ -1,10 +1,234 @@
-use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
 use solver::{
+    annealing::AnnealingConfig,
+    batch::{BatchOptions, solve_batch},
+    dfs::{DfsConfig, Propagation, Restarts, VarOrder},
+    dfs_fork::ForkingDfs,
     solver::{Kind, SolverEngine},
-    sudoku::Sudoku9,
+    sudoku::{Sudoku, Sudoku9},
 };
 use std::hint::black_box;
 
+const CORPUS_SIZE: usize = 1000;
+
+// AI Escargot and Arto Inkala's 2012 puzzle
+const HARD: [&str; 2] = [
+    "1....7.9..3..2...8..96..5....53..9...1..8...26....4...3......1..4......7..7...3..",
+    "8..........36......7..9.2...5...7.......457.....1...3...1....68..85...1..9....4..",
+];
+
+// Random 16x16 puzzles on which the default DFS order takes a very long time
+// while other branching orders finish almost instantly.
+const HEAVY_TAIL: [&str; 2] = [
+    ".A..F8..EG293C..2E.G...C.........3.....7.8..4....F.7.3.24A..G.96EGC..6A.B...D.57....B4D...GE.98.....C..8923D1A..3.D...E...A....B..G1.....C7...4.4..2.73.1B.A9.FC....5...GD9...3....3.91..E...7.59.85....64E..BCG...4DEC5.3.G..1.G..6..8.D9...FE3C7...G6..1F.82..",
+    "678.9.....B.G2EF....8.5.79.D.....3..FG7E26..B.A.B....4...1.A985.4D7...861.2B.C....A...4F6D5.1.7..B.91......4.FD.C.6...AG.F9..B2...E2...4...5..8.94...3..F.D..G....BD.C.8.3..5..4..G........623..DG..C..53.81.7B.E.C.48..D.......8..6...7...FD..G.....E3.B4...5..",
+];
+
+fn parse16(p: &str) -> [[u8; 16]; 16] {
+    let mut init = [[0u8; 16]; 16];
+    for (i, ch) in p.bytes().enumerate() {
+        init[i / 16][i % 16] = match ch {
+            b'.' => 0,
+            b'1'..=b'9' => ch - b'0',
+            _ => ch - b'A' + 10,
+        };
+    }
+    init
+}
+
+fn parse9(p: &str) -> [[u8; 9]; 9] {
+    let mut init = [[0u8; 9]; 9];
+    for (i, ch) in p.bytes().enumerate() {
+        init[i / 9][i % 9] = if ch == b'.' { 0 } else { ch - b'0' };
+    }
+    init
+}
+
+/// `count` puzzles made by emptying `holes` random cells of full grids from annealing.
+/// Seeded, so every run measures the same corpus.
+fn corpus<const N: usize, const BR: usize, const BC: usize>(
+    count: usize,
+    holes: usize,
+) -> Vec<[[u8; N]; N]> {
+    let mut rng = SmallRng::seed_from_u64(count as u64);
+    (0..count)
+        .map(|i| {
+            let mut eng = SolverEngine::<N, BR, BC>::new(Kind::Annealing(AnnealingConfig {
+                seed: i as u64,
+                ..AnnealingConfig::default()
+            }));
+            let mut s = Sudoku::<N, BR, BC>::new([[0; N]; N]);
+            eng.solve(&mut s).unwrap();
+            let mut grid = *s.solution();
+            let mut cells: Vec<usize> = (0..N * N).collect();
+            for k in 0..holes {
+                cells.swap(k, rng.random_range(k..N * N));
+                grid[cells[k] / N][cells[k] % N] = 0;
+            }
+            grid
+        })
+        .collect()
+}
+
+fn bench_corpus<const N: usize, const BR: usize, const BC: usize>(
+    c: &mut Criterion,
+    name: &str,
+    puzzles: &[[[u8; N]; N]],
+) {
+    let kinds = [
+        ("dfs", Kind::Dfs),
+        (
+            "dfs_hidden_singles",
+            Kind::DfsWith(DfsConfig {
+                propagation: Propagation::HiddenSingles,
+                ..DfsConfig::default()
+            }),
+        ),
+    ];
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(puzzles.len() as u64));
+    for (label, kind) in kinds {
+        group.bench_function(label, |b| {
+            let mut eng = SolverEngine::<N, BR, BC>::new(kind.clone());
+            b.iter(|| {
+                for &p in puzzles {
+                    black_box(eng.solve(&mut Sudoku::<N, BR, BC>::new(p))).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_kind<const N: usize, const BR: usize, const BC: usize>(
+    c: &mut Criterion,
+    name: &str,
+    init: [[u8; N]; N],
+) {
+    let with = |propagation| {
+        Kind::DfsWith(DfsConfig {
+            propagation,
+            ..DfsConfig::default()
+        })
+    };
+    let kinds = [
+        ("dfs", Kind::Dfs),
+        ("sat", Kind::Sat),
+        (
+            "portfolio",
+            Kind::Portfolio(vec![
+                Kind::Dfs,
+                Kind::DfsWith(DfsConfig {
+                    propagation: Propagation::HiddenSingles,
+                    var_order: VarOrder::DomWdeg,
+                    ..DfsConfig::default()
+                }),
+                Kind::Sat,
+            ]),
+        ),
+        ("dfs_hidden_singles", with(Propagation::HiddenSingles)),
+        ("dfs_locked_candidates", with(Propagation::LockedCandidates)),
+        (
+            "dfs_hidden_singles_dom_wdeg",
+            Kind::DfsWith(DfsConfig {
+                propagation: Propagation::HiddenSingles,
+                var_order: VarOrder::DomWdeg,
+                ..DfsConfig::default()
+            }),
+        ),
+    ];
+
+    let mut group = c.benchmark_group(name);
+    for (label, kind) in kinds {
+        group.bench_function(label, |b| {
+            let mut eng = SolverEngine::<N, BR, BC>::new(kind.clone());
+            b.iter_batched(
+                || Sudoku::<N, BR, BC>::new(init),
+                |mut s| {
+                    black_box(eng.solve(&mut s)).unwrap();
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    // the node-copying DFS that `Kind::Dfs` replaced, as a baseline
+    group.bench_function("forking_dfs", |b| {
+        let mut dfs = ForkingDfs::<N, BR, BC>::new();
+        b.iter_batched(
+            || Sudoku::<N, BR, BC>::new(init),
+            |mut s| {
+                black_box(dfs.solve(&mut s)).unwrap();
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_restarts(c: &mut Criterion, name: &str, init: [[u8; 16]; 16]) {
+    let ties = DfsConfig {
+        var_order: VarOrder::Mrv,
+        random_ties: true,
+        seed: 1,
+        ..DfsConfig::default()
+    };
+    let kinds = [
+        ("dfs", DfsConfig::default()),
+        ("dfs_random_ties", ties),
+        (
+            "dfs_random_ties_luby",
+            DfsConfig {
+                restarts: Restarts::Luby { unit: 64 },
+                ..ties
+            },
+        ),
+        (
+            "dfs_random_ties_geometric",
+            DfsConfig {
+                restarts: Restarts::Geometric {
+                    first: 64,
+                    factor: 1.5,
+                },
+                ..ties
+            },
+        ),
+        (
+            "dfs_dom_wdeg_luby",
+            DfsConfig {
+                var_order: VarOrder::DomWdeg,
+                restarts: Restarts::Luby { unit: 64 },
+                ..ties
+            },
+        ),
+        (
+            "dfs_learning_luby",
+            DfsConfig {
+                learning: true,
+                restarts: Restarts::Luby { unit: 64 },
+                ..ties
+            },
+        ),
+    ];
+
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for (label, config) in kinds {
+        group.bench_function(label, |b| {
+            let mut eng = SolverEngine::<16, 4, 4>::new(Kind::DfsWith(config));
+            b.iter_batched(
+                || Sudoku::<16, 4, 4>::new(init),
+                |mut s| {
+                    black_box(eng.solve(&mut s)).unwrap();
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
 fn bench_algos(c: &mut Criterion) {
     let init = [
         [9, 0, 6, 3, 4, 0, 8, 1, 0],
@@ -18,16 +242,52 @@ fn bench_algos(c: &mut Criterion) {
         [0, 1, 0, 5, 0, 8, 7, 0, 6],
     ];
 
-    c.bench_function("dfs", |b| {
+    bench_kind::<9, 3, 3>(c, "easy9", init);
+    for (i, p) in HARD.iter().enumerate() {
+        bench_kind::<9, 3, 3>(c, &format!("hard9_{i}"), parse9(p));
+    }
+    bench_kind::<16, 4, 4>(c, "empty16", [[0; 16]; 16]);
+    bench_corpus::<9, 3, 3>(c, "corpus9", &corpus::<9, 3, 3>(300, 58));
+    bench_corpus::<16, 4, 4>(c, "corpus16", &corpus::<16, 4, 4>(30, 125));
+    for (i, p) in HEAVY_TAIL.iter().enumerate() {
+        bench_restarts(c, &format!("heavy16_{i}"), parse16(p));
+    }
+
+    // annealing gives up on the hard puzzles, so it only runs where it finishes
+    let mut group = c.benchmark_group("annealing");
+    group.bench_function("easy9", |b| {
+        let mut eng = SolverEngine::<9, 3, 3>::new(Kind::Annealing(AnnealingConfig::default()));
         b.iter_batched(
             || Sudoku9::new(init),
-            |mut s| {
-                let mut eng = SolverEngine::new(Kind::Dfs);
-                black_box(eng.solve(&mut s)).unwrap();
-            },
+            |mut s| black_box(eng.solve(&mut s)).unwrap(),
             BatchSize::SmallInput,
         )
     });
+    group.bench_function("empty16", |b| {
+        let mut eng = SolverEngine::<16, 4, 4>::new(Kind::Annealing(AnnealingConfig::default()));
+        b.iter_batched(
+            || Sudoku::<16, 4, 4>::new([[0; 16]; 16]),
+            |mut s| black_box(eng.solve(&mut s)).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(CORPUS_SIZE as u64));
+    for threads in [1, 0] {
+        let opts = BatchOptions {
+            threads,
+            ..BatchOptions::default()
+        };
+        group.bench_function(format!("dfs_threads_{threads}"), |b| {
+            b.iter(|| {
+                let puzzles = (0..CORPUS_SIZE).map(|_| Sudoku9::new(init));
+                black_box(solve_batch(Kind::Dfs, puzzles, &opts))
+            })
+        });
+    }
+    group.finish();
 }
 
 criterion_group!(benches, bench_algos);
@@ -0,0 +1,570 @@
+use crate::{dfs::DfsBacktracking, solver::SolveError, sudoku::Sudoku};
+use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+
+/// True if some value repeats in a unit of `grid`.
+fn repeats<const N: usize, const BR: usize, const BC: usize>(grid: &[[u8; N]; N]) -> bool {
+    let cells = grid.as_flattened();
+    (0..3 * N).any(|unit| {
+        let mut seen = 0u16;
+        Sudoku::<N, BR, BC>::unit(unit).iter().any(|&cell| {
+            let v = cells[cell as usize];
+            if v == 0 {
+                return false;
+            }
+            let dup = seen & (1 << (v - 1)) != 0;
+            seen |= 1 << (v - 1);
+            dup
+        })
+    })
+}
+
+/// Finds a minimal set of givens of an unsolvable puzzle that admits no solution on
+/// its own: dropping any one of them makes the rest solvable. Candidate masks are
+/// kept throughout.
+///
+/// Givens are dropped one at a time and kept only if the rest becomes solvable without
+/// them, so the set is minimal but not necessarily the smallest. Costs one solve per given.
+///
+/// Returns Ok(None) if the puzzle has a solution, otherwise the (row, col) of the set,
+/// which is empty when the candidate masks alone leave no solution.
+/// Returns Err(SolveError::InvalidPuzzle) if a given is out of range.
+pub fn conflicting_givens<const N: usize, const BR: usize, const BC: usize>(
+    s: &Sudoku<N, BR, BC>,
+) -> Result<Option<Vec<(usize, usize)>>, SolveError> {
+    let mut dfs = DfsBacktracking::<N, BR, BC>::new();
+    let mut solvable = |grid: &[[u8; N]; N]| -> Result<bool, SolveError> {
+        if repeats::<N, BR, BC>(grid) {
+            return Ok(false);
+        }
+        match dfs.solve(&mut Sudoku::with_candidates(*grid, s.candidates)) {
+            Ok(()) => Ok(true),
+            Err(SolveError::Unsolvable) => Ok(false),
+            Err(e) => Err(e),
+        }
+    };
+
+    let mut grid = s.init;
+    if grid.as_flattened().iter().any(|&v| v as usize > N) {
+        return Err(SolveError::InvalidPuzzle("given is out of range"));
+    }
+    if solvable(&grid)? {
+        return Ok(None);
+    }
+
+    let mut kept = Vec::new();
+    for row in 0..N {
+        for col in 0..N {
+            let val = grid[row][col];
+            if val == 0 {
+                continue;
+            }
+            grid[row][col] = 0;
+            if solvable(&grid)? {
+                grid[row][col] = val;
+                kept.push((row, col));
+            }
+        }
+    }
+    Ok(Some(kept))
+}
+
+/// The digits each cell takes across all solutions of a puzzle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backbone<const N: usize> {
+    /// Bit `val - 1` is set if some solution has `val` at (row, col).
+    pub digits: [[u16; N]; N],
+    /// Solutions the analysis went through, at least 1. Not the total count.
+    pub solutions: u64,
+}
+
+impl<const N: usize> Backbone<N> {
+    /// The value of (row, col) if it is the same in every solution.
+    pub fn forced(&self, row: usize, col: usize) -> Option<u8> {
+        let d = self.digits[row][col];
+        (d.count_ones() == 1).then(|| d.trailing_zeros() as u8 + 1)
+    }
+
+    /// The cells whose value differs between solutions, row-major.
+    pub fn ambiguous(&self) -> Vec<(usize, usize)> {
+        (0..N * N)
+            .map(|i| (i / N, i % N))
+            .filter(|&(row, col)| self.forced(row, col).is_none())
+            .collect()
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.digits
+            .as_flattened()
+            .iter()
+            .all(|d| d.count_ones() == 1)
+    }
+}
+
+/// Finds which cells of `s` are forced and which digits the others take over all its
+/// solutions, candidate masks included, without enumerating every solution.
+///
+/// Every solution found marks its digit in each cell. A cell is then asked for a
+/// solution that avoids all digits marked there so far: one found marks new digits in
+/// many cells at once, none settles the cell. A unique puzzle costs a single search,
+/// others at most one per empty cell plus one per solution found.
+///
+/// Returns Err(SolveError::Unsolvable) if `s` has no solution, or
+/// Err(SolveError::InvalidPuzzle) for the same inputs `solve` rejects.
+pub fn backbone<const N: usize, const BR: usize, const BC: usize>(
+    s: &Sudoku<N, BR, BC>,
+) -> Result<Backbone<N>, SolveError> {
+    let mut dfs = DfsBacktracking::<N, BR, BC>::new();
+    let mut first = Sudoku::with_candidates(s.init, s.candidates);
+    let found = dfs.count_solutions(&mut first, 2)?;
+    if found == 0 {
+        return Err(SolveError::Unsolvable);
+    }
+
+    let mut backbone = Backbone {
+        digits: [[0; N]; N],
+        solutions: 1,
+    };
+    let mark = |backbone: &mut Backbone<N>, solution: &[[u8; N]; N]| {
+        for (digits, &val) in backbone
+            .digits
+            .as_flattened_mut()
+            .iter_mut()
+            .zip(solution.as_flattened())
+        {
+            *digits |= 1 << (val - 1);
+        }
+    };
+    mark(&mut backbone, &first.solution);
+    if found == 1 {
+        return Ok(backbone);
+    }
+
+    for row in 0..N {
+        for col in 0..N {
+            if s.init[row][col] != 0 {
+                continue;
+            }
+            loop {
+                let mut candidates = s.candidates;
+                candidates[row][col] &= !backbone.digits[row][col];
+                if candidates[row][col] == 0 {
+                    break;
+                }
+                let mut other = Sudoku::with_candidates(s.init, candidates);
+                match dfs.solve(&mut other) {
+                    Ok(()) => {
+                        backbone.solutions += 1;
+                        mark(&mut backbone, &other.solution);
+                    }
+                    Err(SolveError::Unsolvable) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+    Ok(backbone)
+}
+
+/// Cells to fill from `solution` so that `s` has it as its only solution. Cells are
+/// added where the solutions disagree most, then each added cell is dropped again if
+/// the rest still pins the solution down, so no cell of the result can be left out.
+/// Empty for a puzzle that is already unique.
+///
+/// Returns Err(SolveError::InvalidPuzzle) if `solution` is not a solution of `s`.
+pub fn clues_for_uniqueness<const N: usize, const BR: usize, const BC: usize>(
+    s: &Sudoku<N, BR, BC>,
+    solution: &[[u8; N]; N],
+) -> Result<Vec<(usize, usize)>, SolveError> {
+    if !Sudoku::<N, BR, BC>::new(*solution).check()
+        || (0..N * N).any(|i| {
+            let (row, col, val) = (i / N, i % N, solution[i / N][i % N]);
+            (s.init[row][col] != 0 && s.init[row][col] != val)
+                || s.candidates[row][col] & (1 << (val - 1)) == 0
+        })
+    {
+        return Err(SolveError::InvalidPuzzle("not a solution of the puzzle"));
+    }
+
+    let mut grid = s.init;
+    let mut added = Vec::new();
+    loop {
+        let b = backbone(&Sudoku::<N, BR, BC>::with_candidates(grid, s.candidates))?;
+        let Some((row, col)) = b.ambiguous().into_iter().max_by_key(|&(row, col)| {
+            (
+                b.digits[row][col].count_ones(),
+                std::cmp::Reverse((row, col)),
+            )
+        }) else {
+            break;
+        };
+        grid[row][col] = solution[row][col];
+        added.push((row, col));
+    }
+
+    let mut dfs = DfsBacktracking::<N, BR, BC>::new();
+    let mut kept = Vec::new();
+    for &(row, col) in added.iter().rev() {
+        grid[row][col] = 0;
+        let mut rest = Sudoku::with_candidates(grid, s.candidates);
+        if dfs.count_solutions(&mut rest, 2)? > 1 {
+            grid[row][col] = solution[row][col];
+            kept.push((row, col));
+        }
+    }
+    kept.sort_unstable();
+    Ok(kept)
+}
+
+/// Givens of a unique puzzle that can each be removed on their own with the puzzle
+/// staying unique. Removing one of them may make others necessary.
+///
+/// Returns Err(SolveError::Unsolvable) without a solution and
+/// Err(SolveError::InvalidPuzzle) if `s` has several solutions.
+pub fn redundant_givens<const N: usize, const BR: usize, const BC: usize>(
+    s: &Sudoku<N, BR, BC>,
+) -> Result<Vec<(usize, usize)>, SolveError> {
+    let mut dfs = DfsBacktracking::<N, BR, BC>::new();
+    let mut whole = Sudoku::with_candidates(s.init, s.candidates);
+    match dfs.count_solutions(&mut whole, 2)? {
+        0 => return Err(SolveError::Unsolvable),
+        1 => {}
+        _ => return Err(SolveError::InvalidPuzzle("puzzle has several solutions")),
+    }
+
+    let mut redundant = Vec::new();
+    let mut grid = s.init;
+    for row in 0..N {
+        for col in 0..N {
+            let val = grid[row][col];
+            if val == 0 {
+                continue;
+            }
+            grid[row][col] = 0;
+            let mut rest = Sudoku::with_candidates(grid, s.candidates);
+            if dfs.count_solutions(&mut rest, 2)? == 1 {
+                redundant.push((row, col));
+            }
+            grid[row][col] = val;
+        }
+    }
+    Ok(redundant)
+}
+
+/// A pattern of clue positions kept by `minimize`: clues are removed together with their
+/// images, so a symmetric puzzle stays symmetric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symmetry {
+    #[default]
+    None,
+    /// Half-turn rotation about the centre.
+    Central,
+    /// Quarter-turn rotation, implies `Central`.
+    Quarter,
+    /// Reflection in the middle row.
+    Horizontal,
+    /// Reflection in the middle column.
+    Vertical,
+    /// Reflection in the main diagonal.
+    Diagonal,
+}
+
+impl Symmetry {
+    fn map<const N: usize>(self, (row, col): (usize, usize)) -> (usize, usize) {
+        match self {
+            Symmetry::None => (row, col),
+            Symmetry::Central => (N - 1 - row, N - 1 - col),
+            Symmetry::Quarter => (col, N - 1 - row),
+            Symmetry::Horizontal => (N - 1 - row, col),
+            Symmetry::Vertical => (row, N - 1 - col),
+            Symmetry::Diagonal => (col, row),
+        }
+    }
+
+    /// The cell and its images, in the order the map reaches them.
+    fn orbit<const N: usize>(self, cell: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut orbit = vec![cell];
+        let mut next = self.map::<N>(cell);
+        while next != cell {
+            orbit.push(next);
+            next = self.map::<N>(next);
+        }
+        orbit
+    }
+}
+
+/// The order in which `minimize` tries to remove clues.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemovalOrder {
+    /// All clues shuffled by a generator with this seed.
+    Seeded(u64),
+    /// These cells first, then the remaining clues row-major. Empty cells are skipped.
+    Listed(Vec<(usize, usize)>),
+}
+
+impl Default for RemovalOrder {
+    fn default() -> Self {
+        RemovalOrder::Seeded(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MinimizeConfig {
+    pub order: RemovalOrder,
+    pub symmetry: Symmetry,
+}
+
+/// Removes clues of a unique puzzle one at a time, keeping each one whose removal would
+/// allow a second solution, until every remaining clue is necessary. Candidate masks are
+/// kept. With a symmetry, clues go together with their images and it is each such group
+/// that ends up necessary, not necessarily each clue.
+///
+/// A clue that is necessary stays necessary as more clues go, so one pass is enough.
+///
+/// Returns Err(SolveError::Unsolvable) without a solution and
+/// Err(SolveError::InvalidPuzzle) if `s` has several solutions.
+pub fn minimize<const N: usize, const BR: usize, const BC: usize>(
+    s: &Sudoku<N, BR, BC>,
+    config: &MinimizeConfig,
+) -> Result<[[u8; N]; N], SolveError> {
+    let mut dfs = DfsBacktracking::<N, BR, BC>::new();
+    let mut whole = Sudoku::with_candidates(s.init, s.candidates);
+    match dfs.count_solutions(&mut whole, 2)? {
+        0 => return Err(SolveError::Unsolvable),
+        1 => {}
+        _ => return Err(SolveError::InvalidPuzzle("puzzle has several solutions")),
+    }
+
+    let mut order: Vec<(usize, usize)> = match &config.order {
+        RemovalOrder::Seeded(seed) => {
+            let mut cells: Vec<_> = (0..N * N).map(|i| (i / N, i % N)).collect();
+            cells.shuffle(&mut SmallRng::seed_from_u64(*seed));
+            cells
+        }
+        RemovalOrder::Listed(cells) => {
+            if cells.iter().any(|&(row, col)| row >= N || col >= N) {
+                return Err(SolveError::InvalidPuzzle("cell is out of range"));
+            }
+            let mut all = cells.clone();
+            all.extend((0..N * N).map(|i| (i / N, i % N)));
+            all
+        }
+    };
+    order.retain(|&(row, col)| s.init[row][col] != 0);
+
+    let mut grid = s.init;
+    let mut tried = [[false; N]; N];
+    for cell in order {
+        if tried[cell.0][cell.1] {
+            continue;
+        }
+        let orbit: Vec<_> = config
+            .symmetry
+            .orbit::<N>(cell)
+            .into_iter()
+            .filter(|&(row, col)| grid[row][col] != 0)
+            .collect();
+        for &(row, col) in &orbit {
+            tried[row][col] = true;
+            grid[row][col] = 0;
+        }
+        let mut rest = Sudoku::with_candidates(grid, s.candidates);
+        if dfs.count_solutions(&mut rest, 2)? > 1 {
+            for &(row, col) in &orbit {
+                grid[row][col] = s.init[row][col];
+            }
+        }
+    }
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sudoku::Sudoku9,
+        test_util::{HARD, parse9},
+    };
+
+    #[test]
+    fn conflicting_givens_are_minimal() {
+        let init = parse9(HARD);
+        assert_eq!(conflicting_givens(&Sudoku9::new(init)), Ok(None));
+
+        // a repeated 8 in the top row is the whole story
+        let mut broken = init;
+        broken[0][5] = 8;
+        assert_eq!(
+            conflicting_givens(&Sudoku9::new(broken)),
+            Ok(Some(vec![(0, 0), (0, 5)]))
+        );
+
+        // a clue consistent with its units but not with the unique solution
+        let mut solved = Sudoku9::new(init);
+        DfsBacktracking::<9, 3, 3>::new()
+            .solve(&mut solved)
+            .unwrap();
+        let mut wrong = init;
+        let (r, c) = (4, 0);
+        wrong[r][c] = (1..=9)
+            .find(|&v| {
+                v != solved.solution[r][c] && {
+                    let mut g = init;
+                    g[r][c] = v;
+                    !repeats::<9, 3, 3>(&g)
+                }
+            })
+            .unwrap();
+        let set = conflicting_givens(&Sudoku9::new(wrong)).unwrap().unwrap();
+        assert!(set.contains(&(r, c)));
+        // without any single clue of the set the rest has a solution
+        let mut dfs = DfsBacktracking::<9, 3, 3>::new();
+        for &(row, col) in &set {
+            let mut rest = [[0u8; 9]; 9];
+            for &(rr, cc) in set.iter().filter(|&&cell| cell != (row, col)) {
+                rest[rr][cc] = wrong[rr][cc];
+            }
+            assert!(dfs.solve(&mut Sudoku9::new(rest)).is_ok());
+        }
+        let mut only = [[0u8; 9]; 9];
+        for &(rr, cc) in &set {
+            only[rr][cc] = wrong[rr][cc];
+        }
+        assert_eq!(
+            dfs.solve(&mut Sudoku9::new(only)),
+            Err(SolveError::Unsolvable)
+        );
+    }
+
+    #[test]
+    fn backbone_matches_per_digit_solves() {
+        let init = parse9(HARD);
+        let unique = backbone(&Sudoku9::new(init)).unwrap();
+        assert!(unique.is_unique());
+        assert_eq!(unique.solutions, 1);
+
+        // without its first three givens the puzzle has several solutions
+        let mut loose = init;
+        for (row, col) in [(0, 0), (1, 2), (1, 3)] {
+            loose[row][col] = 0;
+        }
+        let b = backbone(&Sudoku9::new(loose)).unwrap();
+        assert!(!b.is_unique());
+        assert!(!b.ambiguous().is_empty());
+
+        let mut dfs = DfsBacktracking::<9, 3, 3>::new();
+        for row in 0..9 {
+            for col in 0..9 {
+                if loose[row][col] != 0 {
+                    assert_eq!(b.forced(row, col), Some(loose[row][col]));
+                    continue;
+                }
+                for val in 1..=9u8 {
+                    let mut g = loose;
+                    g[row][col] = val;
+                    let possible =
+                        !repeats::<9, 3, 3>(&g) && dfs.solve(&mut Sudoku9::new(g)).is_ok();
+                    assert_eq!(b.digits[row][col] & (1 << (val - 1)) != 0, possible);
+                }
+            }
+        }
+
+        let mut broken = init;
+        broken[0][5] = 8;
+        assert_eq!(
+            backbone(&Sudoku9::new(broken)),
+            Err(SolveError::InvalidPuzzle("given repeats in a unit"))
+        );
+    }
+
+    #[test]
+    fn uniqueness_repair() {
+        let init = parse9(HARD);
+        let mut solved = Sudoku9::new(init);
+        DfsBacktracking::<9, 3, 3>::new()
+            .solve(&mut solved)
+            .unwrap();
+        assert_eq!(clues_for_uniqueness(&solved, &solved.solution), Ok(vec![]));
+
+        let mut loose = init;
+        for (row, col) in [(0, 0), (1, 2), (1, 3)] {
+            loose[row][col] = 0;
+        }
+        let clues = clues_for_uniqueness(&Sudoku9::new(loose), &solved.solution).unwrap();
+        assert!(!clues.is_empty());
+        let mut dfs = DfsBacktracking::<9, 3, 3>::new();
+        let with = |clues: &[(usize, usize)]| {
+            let mut g = loose;
+            for &(row, col) in clues {
+                g[row][col] = solved.solution[row][col];
+            }
+            Sudoku9::new(g)
+        };
+        assert_eq!(dfs.count_solutions(&mut with(&clues), 2), Ok(1));
+        for i in 0..clues.len() {
+            let mut fewer = clues.clone();
+            fewer.remove(i);
+            assert_eq!(dfs.count_solutions(&mut with(&fewer), 2), Ok(2));
+        }
+
+        let mut wrong = solved.solution;
+        wrong[0].swap(0, 1);
+        assert!(clues_for_uniqueness(&Sudoku9::new(loose), &wrong).is_err());
+
+        // the puzzle is minimal, but one more clue makes clues redundant again
+        assert_eq!(redundant_givens(&Sudoku9::new(init)), Ok(vec![]));
+        let mut extra = init;
+        extra[0][1] = solved.solution[0][1];
+        let redundant = redundant_givens(&Sudoku9::new(extra)).unwrap();
+        assert!(redundant.contains(&(0, 1)));
+        assert_eq!(
+            redundant_givens(&Sudoku9::new(loose)),
+            Err(SolveError::InvalidPuzzle("puzzle has several solutions"))
+        );
+    }
+
+    #[test]
+    fn minimized_puzzles_are_irreducible() {
+        let init = parse9(HARD);
+        let mut solved = Sudoku9::new(init);
+        DfsBacktracking::<9, 3, 3>::new()
+            .solve(&mut solved)
+            .unwrap();
+
+        // extra clues on the diagonals make the puzzle far from minimal
+        let mut padded = init;
+        for i in 0..9 {
+            padded[i][i] = solved.solution[i][i];
+            padded[i][8 - i] = solved.solution[i][8 - i];
+        }
+        for order in [
+            RemovalOrder::Seeded(7),
+            RemovalOrder::Listed(vec![(4, 4), (0, 8)]),
+        ] {
+            let config = MinimizeConfig {
+                order,
+                ..MinimizeConfig::default()
+            };
+            let min = minimize(&Sudoku9::new(padded), &config).unwrap();
+            assert_eq!(redundant_givens(&Sudoku9::new(min)), Ok(vec![]));
+            for (row, col) in (0..81).map(|i| (i / 9, i % 9)) {
+                assert!(min[row][col] == 0 || min[row][col] == padded[row][col]);
+            }
+        }
+
+        let config = MinimizeConfig {
+            order: RemovalOrder::Seeded(1),
+            symmetry: Symmetry::Central,
+        };
+        let min = minimize(&Sudoku9::new(solved.solution), &config).unwrap();
+        let mut dfs = DfsBacktracking::<9, 3, 3>::new();
+        assert_eq!(dfs.count_solutions(&mut Sudoku9::new(min), 2), Ok(1));
+        for (row, col) in (0..81).map(|i| (i / 9, i % 9)) {
+            assert_eq!(min[row][col] == 0, min[8 - row][8 - col] == 0);
+        }
+
+        let mut loose = init;
+        loose[0][0] = 0;
+        loose[1][2] = 0;
+        assert!(minimize(&Sudoku9::new(loose), &MinimizeConfig::default()).is_err());
+    }
+}
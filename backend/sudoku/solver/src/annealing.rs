@@ -0,0 +1,277 @@
+use crate::{
+    cnf,
+    solver::{CancelToken, SolveError},
+    sudoku::Sudoku,
+};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnnealingConfig {
+    /// Seed of the generator behind the initial fill and the moves. The same seed
+    /// and puzzle always produce the same run.
+    pub seed: u64,
+    /// Temperature at the start of every run.
+    pub start_temp: f64,
+    /// The temperature is multiplied by this after every move.
+    pub cooling: f64,
+    /// Moves without a new best cost before the run is abandoned and the boxes are refilled.
+    pub stall_limit: u64,
+    /// Runs tried before giving up with `SolveError::GaveUp`.
+    pub max_restarts: u32,
+}
+
+impl Default for AnnealingConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            start_temp: 1.0,
+            cooling: 0.9999,
+            stall_limit: 50_000,
+            max_restarts: 100,
+        }
+    }
+}
+
+/// Counters of the last `solve` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AnnealingStats {
+    pub moves: u64,
+    pub restarts: u32,
+    /// Lowest cost reached, 0 when solved.
+    pub best_cost: u32,
+}
+
+/// Simulated annealing over box permutations.
+///
+/// Every box is filled with the values its givens leave out, so boxes are always
+/// valid and the cost is the number of values missing from rows and columns, plus the
+/// cells holding a value their candidate mask rules out. A move
+/// swaps two free cells of one box; worse moves are accepted with probability
+/// `exp(-delta / temp)`. A run that stops improving is restarted from a fresh fill.
+/// Incomplete: an unsolvable puzzle ends in `SolveError::GaveUp`, not `Unsolvable`.
+pub struct Annealing<const N: usize, const BR: usize, const BC: usize> {
+    config: AnnealingConfig,
+    rng: SmallRng,
+    grid: [[u8; N]; N],
+    /// Free cells of each box, the cells a move may swap.
+    free: Vec<Vec<(usize, usize)>>,
+    /// How often each value (index `val - 1`) occurs in each row and column.
+    row_counts: [[u8; N]; N],
+    col_counts: [[u8; N]; N],
+    /// Candidate masks of the puzzle being solved.
+    allowed: [[u16; N]; N],
+    stats: AnnealingStats,
+    cancel: CancelToken,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Default for Annealing<N, BR, BC> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Annealing<N, BR, BC> {
+    pub fn new() -> Self {
+        Self::with_config(AnnealingConfig::default())
+    }
+
+    pub fn with_config(config: AnnealingConfig) -> Self {
+        Self {
+            config,
+            rng: SmallRng::seed_from_u64(config.seed),
+            grid: [[0; N]; N],
+            free: vec![Vec::with_capacity(N); N],
+            row_counts: [[0; N]; N],
+            col_counts: [[0; N]; N],
+            allowed: [[0; N]; N],
+            stats: AnnealingStats::default(),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    pub fn stats(&self) -> AnnealingStats {
+        self.stats
+    }
+
+    pub fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    /// Fills the free cells of every box with a random permutation of its missing values
+    /// and recounts rows and columns. Returns the cost of the fill.
+    fn fill(&mut self, init: &[[u8; N]; N]) -> u32 {
+        self.grid = *init;
+        for b in 0..N {
+            let mut missing: u16 = u16::MAX >> (16 - N);
+            for &(r, c) in &self.free[b] {
+                self.grid[r][c] = 0;
+            }
+            let (r0, c0) = ((b / BR) * BR, (b % BR) * BC);
+            for k in 0..N {
+                let v = self.grid[r0 + k / BC][c0 + k % BC];
+                if v > 0 {
+                    missing &= !(1 << (v - 1));
+                }
+            }
+            let mut vals: Vec<u8> = (1..=N as u8)
+                .filter(|v| missing & (1 << (v - 1)) != 0)
+                .collect();
+            // Fisher-Yates
+            for i in (1..vals.len()).rev() {
+                vals.swap(i, self.rng.random_range(0..=i));
+            }
+            for (&(r, c), v) in self.free[b].iter().zip(vals) {
+                self.grid[r][c] = v;
+            }
+        }
+
+        self.row_counts = [[0; N]; N];
+        self.col_counts = [[0; N]; N];
+        for r in 0..N {
+            for c in 0..N {
+                let v = self.grid[r][c] as usize - 1;
+                self.row_counts[r][v] += 1;
+                self.col_counts[c][v] += 1;
+            }
+        }
+        let missing = |counts: &[[u8; N]; N]| -> u32 {
+            counts.iter().flatten().filter(|&&k| k == 0).count() as u32
+        };
+        let ruled_out = (0..N * N)
+            .filter(|&i| self.ruled_out(i / N, i % N, self.grid[i / N][i % N]))
+            .count() as u32;
+        missing(&self.row_counts) + missing(&self.col_counts) + ruled_out
+    }
+
+    fn ruled_out(&self, row: usize, col: usize, val: u8) -> bool {
+        self.allowed[row][col] & (1 << (val - 1)) == 0
+    }
+
+    /// Replaces `from` with `to` at (row, col) in the counts. Returns the change of the cost.
+    fn recount(&mut self, row: usize, col: usize, from: u8, to: u8) -> i32 {
+        let (from, to) = (from as usize - 1, to as usize - 1);
+        let mut delta = 0;
+        for counts in [&mut self.row_counts[row], &mut self.col_counts[col]] {
+            counts[from] -= 1;
+            delta += (counts[from] == 0) as i32;
+            delta -= (counts[to] == 0) as i32;
+            counts[to] += 1;
+        }
+        delta
+    }
+
+    fn swap(&mut self, (r1, c1): (usize, usize), (r2, c2): (usize, usize)) -> i32 {
+        let (a, b) = (self.grid[r1][c1], self.grid[r2][c2]);
+        self.grid[r1][c1] = b;
+        self.grid[r2][c2] = a;
+        let masks = self.ruled_out(r1, c1, b) as i32 - self.ruled_out(r1, c1, a) as i32
+            + self.ruled_out(r2, c2, a) as i32
+            - self.ruled_out(r2, c2, b) as i32;
+        self.recount(r1, c1, a, b) + self.recount(r2, c2, b, a) + masks
+    }
+
+    pub fn solve(&mut self, s: &mut Sudoku<N, BR, BC>) -> Result<(), SolveError> {
+        cnf::check_givens(N, BR, BC, s.init.as_flattened())?;
+        s.check_candidates()?;
+        self.allowed = s.candidates;
+        self.rng = SmallRng::seed_from_u64(self.config.seed);
+        self.stats = AnnealingStats {
+            best_cost: u32::MAX,
+            ..AnnealingStats::default()
+        };
+        for (b, cells) in self.free.iter_mut().enumerate() {
+            let (r0, c0) = ((b / BR) * BR, (b % BR) * BC);
+            cells.clear();
+            cells.extend(
+                (0..N)
+                    .map(|k| (r0 + k / BC, c0 + k % BC))
+                    .filter(|&(r, c)| s.init[r][c] == 0),
+            );
+        }
+        // boxes with two or more free cells, the only ones a move can change
+        let movable: Vec<usize> = (0..N).filter(|&b| self.free[b].len() >= 2).collect();
+
+        for run in 0..=self.config.max_restarts {
+            self.stats.restarts = run;
+            let mut cost = self.fill(&s.init);
+            let mut best = cost;
+            let mut stalled = 0u64;
+            let mut temp = self.config.start_temp;
+
+            while cost > 0 && !movable.is_empty() && stalled < self.config.stall_limit {
+                let b = movable[self.rng.random_range(0..movable.len())];
+                let len = self.free[b].len();
+                let i = self.rng.random_range(0..len);
+                let j = (i + self.rng.random_range(1..len)) % len;
+                let (x, y) = (self.free[b][i], self.free[b][j]);
+
+                let delta = self.swap(x, y);
+                self.stats.moves += 1;
+                if self.stats.moves.is_multiple_of(1024) && self.cancel.is_cancelled() {
+                    return Err(SolveError::Cancelled);
+                }
+                let accept = delta <= 0 || self.rng.random::<f64>() < (-delta as f64 / temp).exp();
+                if accept {
+                    cost = (cost as i32 + delta) as u32;
+                } else {
+                    self.swap(x, y);
+                }
+
+                if cost < best {
+                    best = cost;
+                    stalled = 0;
+                } else {
+                    stalled += 1;
+                }
+                temp = (temp * self.config.cooling).max(f64::MIN_POSITIVE);
+            }
+
+            self.stats.best_cost = self.stats.best_cost.min(best);
+            if cost == 0 {
+                s.solution = self.grid;
+                return Ok(());
+            }
+        }
+
+        Err(SolveError::GaveUp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sudoku::{Sudoku6, Sudoku9};
+
+    #[test]
+    fn annealing_fills_grids_reproducibly() {
+        let fill = |seed| {
+            let mut sa = Annealing::<9, 3, 3>::with_config(AnnealingConfig {
+                seed,
+                ..AnnealingConfig::default()
+            });
+            let mut s = Sudoku9::new([[0; 9]; 9]);
+            assert!(sa.solve(&mut s).is_ok());
+            assert!(s.check());
+            assert_eq!(sa.stats().best_cost, 0);
+            s.solution
+        };
+        assert_eq!(fill(1), fill(1));
+        assert_ne!(fill(1), fill(2));
+    }
+
+    #[test]
+    fn annealing_gives_up_on_unsolvable() {
+        // the top-left cell has no candidate left
+        let mut init = [[0u8; 6]; 6];
+        init[0] = [0, 1, 2, 3, 4, 5];
+        init[3][0] = 6;
+        let mut sa = Annealing::<6, 2, 3>::with_config(AnnealingConfig {
+            stall_limit: 1_000,
+            max_restarts: 3,
+            ..AnnealingConfig::default()
+        });
+        assert_eq!(sa.solve(&mut Sudoku6::new(init)), Err(SolveError::GaveUp));
+        assert_eq!(sa.stats().restarts, 3);
+        assert!(sa.stats().best_cost > 0);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    solver::{Kind, SolveError, SolverEngine},
+    sudoku::Sudoku,
+};
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+pub struct BatchOptions {
+    /// Number of worker threads, `0` means `std::thread::available_parallelism`.
+    pub threads: usize,
+    /// How many puzzles a worker takes from the input at once.
+    pub chunk_size: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            chunk_size: 64,
+        }
+    }
+}
+
+pub struct PuzzleResult<const N: usize> {
+    /// Position of the puzzle in the input iterator.
+    pub index: usize,
+    pub outcome: Result<[[u8; N]; N], SolveError>,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BatchStats {
+    pub total: usize,
+    pub solved: usize,
+    pub unsolvable: usize,
+    pub invalid: usize,
+    /// Puzzles a solver stopped on without an answer: it gave up or was cancelled.
+    pub gave_up: usize,
+    /// Wall clock time of the whole batch.
+    pub wall_time: Duration,
+    /// Sum of per-puzzle solve times over all workers.
+    pub solve_time: Duration,
+    pub max_time: Duration,
+}
+
+impl BatchStats {
+    fn record<const N: usize>(&mut self, res: &PuzzleResult<N>) {
+        self.total += 1;
+        match res.outcome {
+            Ok(_) => self.solved += 1,
+            Err(SolveError::Unsolvable) => self.unsolvable += 1,
+            Err(SolveError::InvalidPuzzle(_)) => self.invalid += 1,
+            Err(SolveError::GaveUp | SolveError::Cancelled) => self.gave_up += 1,
+        }
+        self.solve_time += res.elapsed;
+        self.max_time = self.max_time.max(res.elapsed);
+    }
+
+    fn merge(&mut self, other: &BatchStats) {
+        self.total += other.total;
+        self.solved += other.solved;
+        self.unsolvable += other.unsolvable;
+        self.invalid += other.invalid;
+        self.gave_up += other.gave_up;
+        self.solve_time += other.solve_time;
+        self.max_time = self.max_time.max(other.max_time);
+    }
+
+    pub fn puzzles_per_sec(&self) -> f64 {
+        self.total as f64 / self.wall_time.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+pub struct BatchReport<const N: usize> {
+    /// Per-puzzle results in input order.
+    pub results: Vec<PuzzleResult<N>>,
+    pub stats: BatchStats,
+}
+
+/// Solves every puzzle of `puzzles` on a pool of scoped worker threads.
+///
+/// Each worker owns a single `SolverEngine` for the whole batch, so solver buffers
+/// are allocated once per thread rather than once per puzzle. The input is consumed
+/// lazily in chunks of `opts.chunk_size`.
+pub fn solve_batch<const N: usize, const BR: usize, const BC: usize, I>(
+    kind: Kind,
+    puzzles: I,
+    opts: &BatchOptions,
+) -> BatchReport<N>
+where
+    I: IntoIterator<Item = Sudoku<N, BR, BC>>,
+    I::IntoIter: Send,
+{
+    let threads = match opts.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let chunk_size = opts.chunk_size.max(1);
+    let input = Mutex::new(puzzles.into_iter().enumerate());
+    let started = Instant::now();
+
+    let per_worker: Vec<(Vec<PuzzleResult<N>>, BatchStats)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| solve_worker(kind.clone(), &input, chunk_size)))
+            .collect();
+        workers
+            .into_iter()
+            .map(|w| w.join().expect("batch worker panicked"))
+            .collect()
+    });
+
+    let mut stats = BatchStats::default();
+    let mut results = Vec::with_capacity(per_worker.iter().map(|(r, _)| r.len()).sum());
+    for (worker_results, worker_stats) in per_worker {
+        stats.merge(&worker_stats);
+        results.extend(worker_results);
+    }
+    results.sort_unstable_by_key(|r| r.index);
+    stats.wall_time = started.elapsed();
+
+    BatchReport { results, stats }
+}
+
+fn solve_worker<const N: usize, const BR: usize, const BC: usize, I>(
+    kind: Kind,
+    input: &Mutex<I>,
+    chunk_size: usize,
+) -> (Vec<PuzzleResult<N>>, BatchStats)
+where
+    I: Iterator<Item = (usize, Sudoku<N, BR, BC>)>,
+{
+    let mut engine = SolverEngine::<N, BR, BC>::new(kind);
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut results = Vec::new();
+    let mut stats = BatchStats::default();
+
+    loop {
+        {
+            let mut it = input.lock().expect("batch input poisoned");
+            chunk.extend(it.by_ref().take(chunk_size));
+        }
+        if chunk.is_empty() {
+            break;
+        }
+
+        for (index, mut s) in chunk.drain(..) {
+            let started = Instant::now();
+            let outcome = engine.solve(&mut s).map(|_| s.solution);
+            let res = PuzzleResult {
+                index,
+                outcome,
+                elapsed: started.elapsed(),
+            };
+            stats.record(&res);
+            results.push(res);
+        }
+    }
+
+    (results, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sudoku::Sudoku9;
+
+    const PUZZLE: [[u8; 9]; 9] = [
+        [9, 0, 6, 3, 4, 0, 8, 1, 0],
+        [0, 5, 1, 7, 0, 0, 3, 0, 0],
+        [4, 7, 0, 0, 9, 1, 0, 0, 5],
+        [0, 0, 0, 9, 0, 3, 0, 0, 2],
+        [0, 0, 2, 0, 8, 7, 0, 0, 0],
+        [1, 0, 7, 2, 0, 0, 6, 0, 0],
+        [0, 8, 5, 0, 0, 9, 1, 0, 0],
+        [0, 3, 4, 0, 6, 0, 0, 0, 9],
+        [0, 1, 0, 5, 0, 8, 7, 0, 6],
+    ];
+
+    #[test]
+    fn batch_keeps_input_order_and_counts() {
+        let mut broken = PUZZLE;
+        broken[0][1] = 9; // duplicates the 9 in the same row
+        let puzzles = (0..50).map(|i| Sudoku9::new(if i % 10 == 3 { broken } else { PUZZLE }));
+
+        let opts = BatchOptions {
+            threads: 3,
+            chunk_size: 4,
+        };
+        let report = solve_batch(Kind::Dfs, puzzles, &opts);
+
+        assert_eq!(report.results.len(), 50);
+        assert_eq!(report.stats.total, 50);
+        assert_eq!(report.stats.solved, 45);
+        assert_eq!(report.stats.invalid, 5);
+        for (i, r) in report.results.iter().enumerate() {
+            assert_eq!(r.index, i);
+            assert_eq!(r.outcome.is_err(), i % 10 == 3);
+        }
+    }
+}
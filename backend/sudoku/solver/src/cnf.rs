@@ -0,0 +1,305 @@
+use crate::{solver::SolveError, sudoku::Sudoku};
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+/// A formula in conjunctive normal form, numbered as in DIMACS: variables are
+/// `1..=num_vars` and a literal is a variable or its negation.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Cnf {
+    pub num_vars: usize,
+    pub clauses: Vec<Vec<i32>>,
+}
+
+#[derive(Debug, Error)]
+pub enum DimacsError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Puzzle(#[from] SolveError),
+
+    #[error("solver reported the formula unsatisfiable")]
+    Unsatisfiable,
+
+    #[error("malformed model: {0}")]
+    Malformed(String),
+}
+
+impl Cnf {
+    /// Writes the formula in DIMACS CNF format, each of `comments` as a `c` line before the header.
+    pub fn write_dimacs(&self, mut w: impl Write, comments: &[&str]) -> io::Result<()> {
+        for c in comments {
+            writeln!(w, "c {c}")?;
+        }
+        writeln!(w, "p cnf {} {}", self.num_vars, self.clauses.len())?;
+        for clause in &self.clauses {
+            for lit in clause {
+                write!(w, "{lit} ")?;
+            }
+            writeln!(w, "0")?;
+        }
+        w.flush()
+    }
+}
+
+/// Reads the literals of a model as printed by SAT solvers: either competition style
+/// (`s SATISFIABLE` followed by `v ...` lines) or a plain `SAT` line followed by the literals.
+/// Comment lines start with `c`, the literal list may end with `0`.
+pub fn read_model(r: impl BufRead) -> Result<Vec<i32>, DimacsError> {
+    let mut lits = Vec::new();
+    for line in r.lines() {
+        let line = line?;
+        let line = line.trim();
+        let body = match line.split_once(char::is_whitespace) {
+            Some(("v", rest)) => rest,
+            _ if line == "v" => "",
+            _ if line.is_empty() || line.starts_with('c') => continue,
+            _ if line.contains("UNSAT") => return Err(DimacsError::Unsatisfiable),
+            _ if line.starts_with('s') || line == "SAT" => continue,
+            _ => line,
+        };
+        for tok in body.split_whitespace() {
+            match tok.parse::<i32>() {
+                Ok(0) => return Ok(lits),
+                Ok(lit) => lits.push(lit),
+                Err(_) => return Err(DimacsError::Malformed(format!("unexpected token {tok:?}"))),
+            }
+        }
+    }
+    if lits.is_empty() {
+        return Err(DimacsError::Malformed("no literals".into()));
+    }
+    Ok(lits)
+}
+
+/// Variable meaning "cell (row, col) holds `val`" in a grid of size `n`, `val` in 1..=n.
+pub fn cell_var(n: usize, row: usize, col: usize, val: u8) -> i32 {
+    ((row * n + col) * n + val as usize) as i32
+}
+
+/// Cells of the rows, columns and boxes of a grid of size `n` with `br` x `bc` boxes.
+pub(crate) fn units(n: usize, br: usize, bc: usize) -> impl Iterator<Item = Vec<(usize, usize)>> {
+    let rows = (0..n).map(move |r| (0..n).map(|c| (r, c)).collect());
+    let cols = (0..n).map(move |c| (0..n).map(|r| (r, c)).collect());
+    let boxes = (0..n).map(move |b| {
+        let (r0, c0) = ((b / br) * br, (b % br) * bc);
+        (0..n).map(|k| (r0 + k / bc, c0 + k % bc)).collect()
+    });
+    rows.chain(cols).chain(boxes)
+}
+
+/// Checks the givens of a row-major grid of size `n` with `br` x `bc` boxes.
+/// Returns Err(SolveError::InvalidPuzzle) if the shape is inconsistent or a given is out of range or repeats in a unit.
+pub fn check_givens(n: usize, br: usize, bc: usize, givens: &[u8]) -> Result<(), SolveError> {
+    if n == 0 || n > 64 || br * bc != n || givens.len() != n * n {
+        return Err(SolveError::InvalidPuzzle(
+            "grid does not match its box shape",
+        ));
+    }
+    if givens.iter().any(|&v| v as usize > n) {
+        return Err(SolveError::InvalidPuzzle("given is out of range"));
+    }
+    for unit in units(n, br, bc) {
+        let mut seen = 0u64;
+        for (r, c) in unit {
+            let v = givens[r * n + c];
+            if v > 0 {
+                if seen & (1 << (v - 1)) != 0 {
+                    return Err(SolveError::InvalidPuzzle("given repeats in a unit"));
+                }
+                seen |= 1 << (v - 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encodes a row-major grid of size `n` with `br` x `bc` boxes, `0` for an empty cell.
+///
+/// Every cell holds exactly one value, every unit holds each value exactly once
+/// (at-least-one clauses plus pairwise at-most-one clauses), and every given is a unit clause.
+pub fn encode(n: usize, br: usize, bc: usize, givens: &[u8]) -> Result<Cnf, SolveError> {
+    check_givens(n, br, bc, givens)?;
+    let mut clauses = Vec::new();
+    let mut exactly_one = |lits: Vec<i32>| {
+        for (i, &a) in lits.iter().enumerate() {
+            for &b in &lits[i + 1..] {
+                clauses.push(vec![-a, -b]);
+            }
+        }
+        clauses.push(lits);
+    };
+
+    for r in 0..n {
+        for c in 0..n {
+            exactly_one((1..=n as u8).map(|v| cell_var(n, r, c, v)).collect());
+        }
+    }
+    for unit in units(n, br, bc) {
+        for v in 1..=n as u8 {
+            exactly_one(unit.iter().map(|&(r, c)| cell_var(n, r, c, v)).collect());
+        }
+    }
+    for (i, &v) in givens.iter().enumerate() {
+        if v > 0 {
+            clauses.push(vec![cell_var(n, i / n, i % n, v)]);
+        }
+    }
+
+    Ok(Cnf {
+        num_vars: n * n * n,
+        clauses,
+    })
+}
+
+/// Reads the grid of size `n` back from a model, `model(var)` telling whether a variable is true.
+/// Returns None if some cell does not hold exactly one value.
+pub fn decode(n: usize, model: impl Fn(i32) -> bool) -> Option<Vec<u8>> {
+    let mut grid = Vec::with_capacity(n * n);
+    for r in 0..n {
+        for c in 0..n {
+            let mut vals = (1..=n as u8).filter(|&v| model(cell_var(n, r, c, v)));
+            match (vals.next(), vals.next()) {
+                (Some(v), None) => grid.push(v),
+                _ => return None,
+            }
+        }
+    }
+    Some(grid)
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Sudoku<N, BR, BC> {
+    /// The CNF of the puzzle's rules and givens, see `encode`, plus a negative unit
+    /// clause for every value an empty cell's candidate mask rules out.
+    pub fn to_cnf(&self) -> Result<Cnf, SolveError> {
+        self.check_candidates()?;
+        let mut cnf = encode(N, BR, BC, self.init.as_flattened())?;
+        for (i, (&v, &mask)) in self
+            .init
+            .as_flattened()
+            .iter()
+            .zip(self.candidates.as_flattened())
+            .enumerate()
+        {
+            if v == 0 {
+                let ruled_out = (1..=N as u8).filter(|&val| mask & (1 << (val - 1)) == 0);
+                cnf.clauses
+                    .extend(ruled_out.map(|val| vec![-cell_var(N, i / N, i % N, val)]));
+            }
+        }
+        Ok(cnf)
+    }
+
+    /// Writes the puzzle as a DIMACS CNF file. Variable `(row * N + col) * N + val`
+    /// (0-based row and column) means the cell holds `val`, see `cell_var`.
+    pub fn write_dimacs(&self, w: impl Write) -> Result<(), DimacsError> {
+        let header =
+            format!("sudoku {N}x{N}, {BR}x{BC} boxes, var = (row * {N} + col) * {N} + val");
+        self.to_cnf()?.write_dimacs(w, &[&header])?;
+        Ok(())
+    }
+
+    /// Takes the solution from a model of the `write_dimacs` formula, see `read_model`.
+    /// The grid is not checked against the rules, use `check` for that. A variable beyond
+    /// `N * N * N`, e.g. from a model of another grid size, is `DimacsError::Malformed`.
+    pub fn read_model(&mut self, r: impl BufRead) -> Result<(), DimacsError> {
+        let mut model = vec![false; N * N * N + 1];
+        for lit in read_model(r)? {
+            let v = model.get_mut(lit.unsigned_abs() as usize).ok_or_else(|| {
+                DimacsError::Malformed(format!("variable {} is out of range", lit.unsigned_abs()))
+            })?;
+            *v = lit > 0;
+        }
+        let grid = decode(N, |v| model[v as usize])
+            .ok_or_else(|| DimacsError::Malformed("a cell holds no value or several".into()))?;
+        if self
+            .init
+            .as_flattened()
+            .iter()
+            .zip(&grid)
+            .any(|(&g, &v)| g != 0 && g != v)
+        {
+            return Err(DimacsError::Malformed("model contradicts a given".into()));
+        }
+        for (i, v) in grid.into_iter().enumerate() {
+            self.solution[i / N][i % N] = v;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        solver::{Kind, SolverEngine},
+        sudoku::Sudoku6,
+    };
+
+    #[test]
+    fn encode_counts_clauses_and_rejects_bad_givens() {
+        let cnf = encode(4, 2, 2, &[0; 16]).unwrap();
+        assert_eq!(cnf.num_vars, 64);
+        // 16 cells and 4 * 12 unit-values, each with one ALO and 6 pairwise AMO clauses
+        assert_eq!(cnf.clauses.len(), (16 + 48) * 7);
+
+        let mut givens = [0u8; 16];
+        givens[0] = 1;
+        givens[5] = 1; // same box as cell 0
+        assert_eq!(
+            encode(4, 2, 2, &givens),
+            Err(SolveError::InvalidPuzzle("given repeats in a unit"))
+        );
+        assert!(encode(4, 2, 3, &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn dimacs_round_trip() {
+        let init = [
+            [0, 0, 3, 0, 0, 0],
+            [0, 0, 0, 0, 2, 0],
+            [0, 0, 0, 5, 0, 0],
+            [0, 6, 0, 0, 0, 0],
+            [0, 0, 0, 0, 0, 4],
+            [1, 0, 0, 0, 0, 0],
+        ];
+        let mut out = Vec::new();
+        Sudoku6::new(init).write_dimacs(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let header = text.lines().find(|l| l.starts_with('p')).unwrap();
+        assert_eq!(header, format!("p cnf 216 {}", (36 + 18 * 6) * 16 + 6));
+
+        // stand in for an external solver with the model of our own solution
+        let mut solved = Sudoku6::new(init);
+        SolverEngine::new(Kind::Dfs).solve(&mut solved).unwrap();
+        let mut output = String::from("c external solver\ns SATISFIABLE\n");
+        for (i, &v) in solved.solution.as_flattened().iter().enumerate() {
+            for val in 1..=6 {
+                let lit = cell_var(6, i / 6, i % 6, val);
+                output += &format!("v {}\n", if val == v { lit } else { -lit });
+            }
+        }
+        output += "v 0\n";
+
+        let mut s = Sudoku6::new(init);
+        s.read_model(output.as_bytes()).unwrap();
+        assert!(s.check());
+        assert_eq!(s.solution, solved.solution);
+
+        assert!(matches!(
+            s.read_model("s UNSATISFIABLE\n".as_bytes()),
+            Err(DimacsError::Unsatisfiable)
+        ));
+        // a model of a larger grid's formula
+        let wider = output.replace("v 0\n", &format!("v {} 0\n", 6 * 6 * 6 + 1));
+        assert!(matches!(
+            s.read_model(wider.as_bytes()),
+            Err(DimacsError::Malformed(_))
+        ));
+        let partial = format!("SAT\n{} 0\n", cell_var(6, 0, 2, 4));
+        assert!(matches!(
+            s.read_model(partial.as_bytes()),
+            Err(DimacsError::Malformed(_))
+        ));
+    }
+}
@@ -1,54 +1,355 @@
-use crate::{solver::SolveError, sudoku::Sudoku};
-use std::collections::VecDeque;
+use crate::{
+    solver::{CancelToken, SolveError},
+    sudoku::{MAX_CELLS, Sudoku},
+};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use std::{cmp::Ordering, collections::VecDeque};
 
 struct Constraints<const N: usize, const BR: usize, const BC: usize> {
     row: [u16; N],
     col: [u16; N],
     s_box: [u16; N],
-    backtrack: [[u16; N]; N],
+    banned: [[u16; N]; N],
+    /// Candidates of each cell, indexed by `row * N + col` and kept up to date by every
+    /// change, so reading a domain is a lookup. 0 for a filled cell.
+    cand: [u16; MAX_CELLS],
+    /// `u16::MAX` for an empty cell and 0 for a filled one (or past the grid), lane by lane with `cand`.
+    open: [u16; MAX_CELLS],
+    /// One entry per assignment or ban, in trail order: the peers (bit `k` for the `k`-th
+    /// peer) that lost the value and the cell's own `cand` before the change, so undoing
+    /// restores them instead of recomputing.
+    saved: Vec<(u64, u16)>,
 }
 
 impl<const N: usize, const BR: usize, const BC: usize> Constraints<N, BR, BC> {
-    const FULL_MASK_N: u16 = (1u16 << N) - 1; // Set N bits
+    const FULL_MASK_N: u16 = u16::MAX >> (16 - N); // Set N bits, N <= 16
 
-    fn fork(&self) -> Constraints<N, BR, BC> {
-        Constraints {
-            row: self.row,
-            col: self.col,
-            s_box: self.s_box,
-            backtrack: [[0; N]; N],
+    fn new() -> Self {
+        let mut cons = Self {
+            row: [0; N],
+            col: [0; N],
+            s_box: [0; N],
+            banned: [[0; N]; N],
+            cand: [0; MAX_CELLS],
+            open: [0; MAX_CELLS],
+            saved: Vec::with_capacity(N * N),
+        };
+        cons.clear();
+        cons.refresh();
+        cons
+    }
+
+    /// Empties the grid. `cand` is stale until `refresh`.
+    fn clear(&mut self) {
+        self.row = [0; N];
+        self.col = [0; N];
+        self.s_box = [0; N];
+        self.banned = [[0; N]; N];
+        self.open[..N * N].fill(u16::MAX);
+        self.saved.clear();
+    }
+
+    /// Places a given without touching `cand`, cheaper than `mark_taken` when loading a
+    /// whole grid. Cannot be undone.
+    fn place_given(&mut self, row: usize, col: usize, val: u8) {
+        let mark_mask = 1u16 << (val - 1);
+        self.s_box[Sudoku::<N, BR, BC>::box_index(row, col)] |= mark_mask;
+        self.col[col] |= mark_mask;
+        self.row[row] |= mark_mask;
+        self.open[row * N + col] = 0;
+    }
+
+    /// Recomputes every `cand` from the unit masks and bans.
+    fn refresh(&mut self) {
+        for cell in 0..N * N {
+            let (row, col) = (cell / N, cell % N);
+            self.cand[cell] = self.open[cell]
+                & Self::FULL_MASK_N
+                & !(self.placed(row, col) | self.banned[row][col]);
         }
     }
 
-    fn mark_taken(&mut self, row: usize, col: usize, val: u8) {
+    /// Values placed in the row, column or box of a cell.
+    #[inline]
+    fn placed(&self, row: usize, col: usize) -> u16 {
+        self.s_box[Sudoku::<N, BR, BC>::box_index(row, col)] | self.col[col] | self.row[row]
+    }
+
+    /// Places `val` and removes it from the peers.
+    /// Returns the peers (bit `k` for the `k`-th) it left with a single candidate.
+    fn mark_taken(&mut self, row: usize, col: usize, val: u8) -> u64 {
         let mark_mask = 1u16 << (val - 1);
         self.s_box[Sudoku::<N, BR, BC>::box_index(row, col)] |= mark_mask;
         self.col[col] |= mark_mask;
         self.row[row] |= mark_mask;
+
+        let cell = row * N + col;
+        let (mut lost, mut singles) = (0u64, 0u64);
+        for (k, &p) in Sudoku::<N, BR, BC>::peers(cell).iter().enumerate() {
+            let m = self.cand[p as usize];
+            let left = m & !mark_mask;
+            lost |= ((m != left) as u64) << k;
+            singles |= ((m != left && left.is_power_of_two()) as u64) << k;
+            self.cand[p as usize] = left;
+        }
+        self.saved.push((lost, self.cand[cell]));
+        self.cand[cell] = 0;
+        self.open[cell] = 0;
+        singles
+    }
+
+    fn unmark_taken(&mut self, row: usize, col: usize, val: u8) {
+        let mark_mask = 1u16 << (val - 1);
+        self.s_box[Sudoku::<N, BR, BC>::box_index(row, col)] &= !mark_mask;
+        self.col[col] &= !mark_mask;
+        self.row[row] &= !mark_mask;
+
+        let cell = row * N + col;
+        let (mut lost, own) = self.saved.pop().expect("unmark without a mark");
+        self.cand[cell] = own;
+        self.open[cell] = u16::MAX;
+        while lost != 0 {
+            let p = Sudoku::<N, BR, BC>::peers(cell)[lost.trailing_zeros() as usize];
+            self.cand[p as usize] |= mark_mask;
+            lost &= lost - 1;
+        }
     }
 
-    fn forbid_cell_val(&mut self, row: usize, col: usize, val: u8) {
-        self.backtrack[row][col] |= 1u16 << (val - 1);
+    fn ban(&mut self, row: usize, col: usize, mask: u16) {
+        let cell = row * N + col;
+        self.banned[row][col] |= mask;
+        self.saved.push((0, self.cand[cell]));
+        self.cand[cell] &= !mask;
     }
 
-    fn forbidden_candidates(&self, row: usize, col: usize) -> u16 {
-        Self::FULL_MASK_N
-            & (self.s_box[Sudoku::<N, BR, BC>::box_index(row, col)]
-                | self.col[col]
-                | self.row[row]
-                | self.backtrack[row][col])
+    fn unban(&mut self, row: usize, col: usize, prev: u16) {
+        self.banned[row][col] = prev;
+        let (_, own) = self.saved.pop().expect("unban without a ban");
+        self.cand[row * N + col] = own;
     }
 
+    #[inline]
     fn available_candidates(&self, row: usize, col: usize) -> u16 {
-        Self::FULL_MASK_N & !self.forbidden_candidates(row, col)
+        self.cand[row * N + col]
+    }
+
+    /// Fewest candidates of an empty cell: 0 if some empty cell has none,
+    /// `u16::MAX` if the grid is full.
+    #[cfg(not(feature = "simd"))]
+    fn min_open_count(&self) -> u16 {
+        self.cand[..N * N]
+            .iter()
+            .zip(&self.open)
+            .map(|(&m, &open)| m.count_ones() as u16 | !open)
+            .min()
+            .unwrap_or(u16::MAX)
+    }
+
+    /// Fewest candidates of an empty cell: 0 if some empty cell has none,
+    /// `u16::MAX` if the grid is full.
+    #[cfg(feature = "simd")]
+    fn min_open_count(&self) -> u16 {
+        use std::simd::{cmp::SimdOrd, num::SimdUint, u16x16};
+
+        // cells past the grid are closed, so whole chunks can be read
+        let (cand, _) = self.cand.as_chunks::<16>();
+        let (open, _) = self.open.as_chunks::<16>();
+        let mut min = u16x16::splat(u16::MAX);
+        for (m, o) in cand.iter().zip(open).take((N * N).div_ceil(16)) {
+            let counts = u16x16::from_array(*m).count_ones();
+            min = min.simd_min(counts | !u16x16::from_array(*o));
+        }
+        min.reduce_min()
+    }
+}
+
+/// How much propagation runs at every search node. Stronger levels guess less
+/// but spend more time per node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Propagation {
+    /// Cells with a single candidate only.
+    #[default]
+    NakedSingles,
+    /// Also values with a single possible cell in a row, column or box.
+    HiddenSingles,
+    /// Also pointing and claiming eliminations between boxes and lines.
+    LockedCandidates,
+}
+
+/// Which empty cell the search branches on next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VarOrder {
+    /// First empty cell in row-major order.
+    Natural,
+    /// Fewest candidates, ties broken by row-major order.
+    Mrv,
+    /// Fewest candidates, ties broken by more empty peers and then by fewer peer candidates.
+    #[default]
+    MrvDegree,
+    /// Smallest ratio of candidates to the summed weights of the cell's units.
+    /// A unit's weight grows every time propagation fails on it.
+    DomWdeg,
+    /// Uniformly random empty cell, drawn from the seeded generator.
+    Random,
+}
+
+/// Which candidate of the chosen cell is tried first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueOrder {
+    /// Smallest candidate first.
+    Natural,
+    /// Least constraining value: the candidate that appears in the fewest peer domains.
+    #[default]
+    Lcv,
+    /// Uniformly random candidate, drawn from the seeded generator.
+    Random,
+}
+
+/// When the search gives up on the current branch and starts again from the givens.
+/// Cutoffs count backtracks since the last restart and grow without bound, so a
+/// search with restarts stays complete.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Restarts {
+    #[default]
+    Never,
+    /// The n-th cutoff is `unit * luby(n)`, i.e. `unit` times 1, 1, 2, 1, 1, 2, 4, 1, ...
+    Luby { unit: u64 },
+    /// The n-th cutoff is `first * factor^n`, and at least `first + n`, so it grows
+    /// even with a `factor` of 1 or less.
+    Geometric { first: u64, factor: f64 },
+}
+
+impl Restarts {
+    /// Backtrack budget of the `n`-th run (0-based), `None` for no limit.
+    fn cutoff(&self, n: u32) -> Option<u64> {
+        match *self {
+            Restarts::Never => None,
+            Restarts::Luby { unit } => Some(unit.max(1).saturating_mul(luby(n as u64 + 1))),
+            Restarts::Geometric { first, factor } => {
+                let first = first.max(1);
+                let grown = (first as f64 * factor.max(1.0).powi(n as i32)) as u64;
+                Some(grown.max(first.saturating_add(n as u64)))
+            }
+        }
+    }
+}
+
+/// The Luby sequence 1, 1, 2, 1, 1, 2, 4, 1, 1, 2, 1, 1, 2, 4, 8, ... (1-based).
+pub(crate) fn luby(mut i: u64) -> u64 {
+    loop {
+        // smallest k with i <= 2^k - 1
+        let k = 64 - i.leading_zeros();
+        if i == (1u64 << k) - 1 {
+            return 1u64 << (k - 1);
+        }
+        i -= (1u64 << (k - 1)) - 1;
     }
 }
 
-struct ChoosenVal {
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DfsConfig {
+    pub propagation: Propagation,
+    pub var_order: VarOrder,
+    pub value_order: ValueOrder,
+    /// Break ties of `var_order` and of LCV at random instead of by position.
+    pub random_ties: bool,
+    pub restarts: Restarts,
+    /// Learn a nogood from every conflict and backjump over decisions that took no part
+    /// in it. Nogoods are kept for the whole search, across restarts.
+    pub learning: bool,
+    /// Seed of the generator behind the random orders and tie breaks. The same seed
+    /// and puzzle always produce the same search.
+    pub seed: u64,
+}
+
+/// Counters of the last `solve` or `count_solutions` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchStats {
+    pub decisions: u64,
+    pub backtracks: u64,
+    pub restarts: u32,
+    /// Nogoods learned from conflicts, see `DfsConfig::learning`.
+    pub learned: u64,
+}
+
+/// Why a puzzle or pencilmark state cannot be completed, as found by propagation alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contradiction {
+    /// The empty cell at (row, col) has no candidate left.
+    NoCandidates { row: usize, col: usize },
+    /// `val` has no cell left in `unit`, numbered as in `Sudoku::unit`.
+    NoPlace { unit: usize, val: u8 },
+}
+
+/// A reversible change of the search state.
+enum TrailEntry {
+    Assign { row: usize, col: usize },
+    Ban { row: usize, col: usize, prev: u16 },
+}
+
+/// A branching decision together with the trail length to restore when it is undone.
+struct Decision {
     row: usize,
     col: usize,
     val: u8,
+    trail_len: usize,
+}
+
+/// Where propagation last detected a contradiction.
+#[derive(Clone, Copy)]
+enum Conflict {
+    /// An empty cell without candidates.
+    Cell(usize, usize),
+    /// A value with no cell left in a unit.
+    Unit { unit: usize, val: u8 },
+    /// A learned nogood with all of its assignments made.
+    Nogood(usize),
+}
+
+/// Why a cell got its value or lost a candidate, kept for conflict analysis.
+#[derive(Clone, Copy)]
+enum Reason {
+    Given,
+    Decision,
+    /// A failed value banned by chronological backtracking.
+    Search,
+    NakedSingle,
+    HiddenSingle(usize),
+    /// The value is confined to box `b` on `line` and was removed from the rest of the line.
+    Pointing {
+        b: usize,
+        line: usize,
+    },
+    /// The value is confined to `line` in box `b` and was removed from the rest of the box.
+    Claiming {
+        b: usize,
+        line: usize,
+    },
+    Nogood(usize),
+}
+
+/// A fact conflict analysis still has to explain: a cell's value, or a missing candidate.
+enum Cause {
+    Assign(usize),
+    Elim(usize, u8),
+}
+
+/// Learned nogoods, each a set of assignments that cannot all hold at once.
+/// An assignment is stored as the literal `cell * N + val - 1`, `cell = row * N + col`.
+/// Every nogood of two or more literals watches two literals that are not assigned:
+/// when one of them is, the watch moves to another one or, if all the others are
+/// assigned already, the last literal is banned.
+#[derive(Default)]
+struct Nogoods {
+    lits: Vec<Vec<u32>>,
+    /// Ids of the nogoods watching each literal.
+    watches: Vec<Vec<usize>>,
+    /// Ids of the single-literal nogoods, applied on every propagation.
+    units: Vec<usize>,
+    /// Trail position up to which assignments were checked against the watches.
+    head: usize,
 }
+
 struct PeerStat {
     peers_count: u8,
     peers_domains_sum: u16,
@@ -59,69 +360,168 @@ enum MrvRes {
     Cell(usize, usize),
 }
 
-struct DfsNode<const N: usize, const BR: usize, const BC: usize> {
+/// The single mutable state of the search. Every change goes through `assign` or `ban`
+/// and is recorded on the trail, so backtracking is `undo_to` instead of a node copy.
+struct SearchState<const N: usize, const BR: usize, const BC: usize> {
     grid: [[u8; N]; N],
-    choice: Option<ChoosenVal>,
     constraints: Constraints<N, BR, BC>,
+    trail: Vec<TrailEntry>,
+    conflict: Option<Conflict>,
+    /// Trail stamp (trail length after the change, 0 for givens) and reason of the value
+    /// of each cell, indexed by `row * N + col`.
+    assigned_at: Vec<(u32, Reason)>,
+    /// Stamp and reason of each banned candidate, indexed by `(row * N + col) * N + val - 1`.
+    banned_at: Vec<(u32, Reason)>,
+    nogoods: Nogoods,
 }
 
-impl<const N: usize, const BR: usize, const BC: usize> DfsNode<N, BR, BC> {
-    fn new(grid: [[u8; N]; N]) -> Self {
-        let mut state = Self {
-            grid,
-            constraints: Constraints {
-                row: [0; N],
-                col: [0; N],
-                s_box: [0; N],
-                backtrack: [[0; N]; N],
+impl<const N: usize, const BR: usize, const BC: usize> SearchState<N, BR, BC> {
+    fn new() -> Self {
+        Self {
+            grid: [[0; N]; N],
+            constraints: Constraints::new(),
+            trail: Vec::with_capacity(N * N),
+            conflict: None,
+            assigned_at: vec![(0, Reason::Given); N * N],
+            banned_at: vec![(0, Reason::Given); N * N * N],
+            nogoods: Nogoods {
+                watches: vec![Vec::new(); N * N * N],
+                ..Nogoods::default()
             },
-            choice: None,
-        };
+        }
+    }
+
+    /// Loads the givens and candidate masks, dropping whatever the previous search left behind.
+    /// Returns Err(SolveError::InvalidPuzzle) if a given is out of range, repeats in a unit
+    /// or is missing from its candidate mask.
+    fn reset(&mut self, s: &Sudoku<N, BR, BC>) -> Result<(), SolveError> {
+        s.check_candidates()?;
+        self.grid = s.init;
+        self.constraints.clear();
+        self.trail.clear();
+        self.conflict = None;
+        self.nogoods.lits.clear();
+        self.nogoods.watches.iter_mut().for_each(Vec::clear);
+        self.nogoods.units.clear();
+        self.nogoods.head = 0;
 
         for row in 0..N {
             for col in 0..N {
-                if state.grid[row][col] > 0 {
-                    let taken_bit = 1u16 << (state.grid[row][col] - 1);
-                    state.constraints.col[col] |= taken_bit;
-                    state.constraints.row[row] |= taken_bit;
-                    state.constraints.s_box[Sudoku::<N, BR, BC>::box_index(row, col)] |= taken_bit;
+                let val = self.grid[row][col];
+                if val > 0 {
+                    if val as usize > N {
+                        return Err(SolveError::InvalidPuzzle("given is out of range"));
+                    }
+                    if self.constraints.placed(row, col) & (1u16 << (val - 1)) != 0 {
+                        return Err(SolveError::InvalidPuzzle("given repeats in a unit"));
+                    }
+                    self.constraints.place_given(row, col, val);
+                    self.assigned_at[row * N + col] = (0, Reason::Given);
+                } else {
+                    // restricted candidates are bans that nothing undoes
+                    let mut ruled_out =
+                        Constraints::<N, BR, BC>::FULL_MASK_N & !s.candidates[row][col];
+                    self.constraints.banned[row][col] = ruled_out;
+                    while ruled_out != 0 {
+                        let v = ruled_out.trailing_zeros() as usize;
+                        self.banned_at[(row * N + col) * N + v] = (0, Reason::Given);
+                        ruled_out &= ruled_out - 1;
+                    }
                 }
             }
         }
+        self.constraints.refresh();
 
-        state
+        Ok(())
     }
 
-    fn fork(&mut self, row: usize, col: usize, val: u8) -> Self {
-        let mut node = DfsNode {
-            grid: self.grid,
-            choice: Some(ChoosenVal { row, col, val }),
-            constraints: Constraints::fork(&self.constraints),
-        };
-        node.constraints.mark_taken(row, col, val);
-        node.grid[row][col] = val;
+    /// Returns the peers left with a single candidate, see `Constraints::mark_taken`.
+    fn assign(&mut self, row: usize, col: usize, val: u8, reason: Reason) -> u64 {
+        self.grid[row][col] = val;
+        let singles = self.constraints.mark_taken(row, col, val);
+        self.trail.push(TrailEntry::Assign { row, col });
+        self.assigned_at[row * N + col] = (self.trail.len() as u32, reason);
+        singles
+    }
 
-        node
+    fn ban(&mut self, row: usize, col: usize, val: u8, reason: Reason) {
+        self.ban_mask(row, col, 1u16 << (val - 1), reason);
+    }
+
+    fn ban_mask(&mut self, row: usize, col: usize, mask: u16, reason: Reason) {
+        let prev = self.constraints.banned[row][col];
+        self.constraints.ban(row, col, mask);
+        self.trail.push(TrailEntry::Ban { row, col, prev });
+
+        let stamp = self.trail.len() as u32;
+        let mut fresh = mask & !prev;
+        while fresh != 0 {
+            let v = fresh.trailing_zeros() as usize;
+            self.banned_at[(row * N + col) * N + v] = (stamp, reason);
+            fresh &= fresh - 1;
+        }
+    }
+
+    /// Bans `mask` in an empty cell if any of it is still available.
+    /// Returns true if the cell lost a candidate.
+    fn eliminate(&mut self, row: usize, col: usize, mask: u16, reason: Reason) -> bool {
+        if self.grid[row][col] != 0 || self.constraints.available_candidates(row, col) & mask == 0 {
+            return false;
+        }
+        self.ban_mask(row, col, mask, reason);
+        true
+    }
+
+    /// Cell `k` of unit `unit`: units 0..N are rows, N..2N columns and 2N..3N boxes.
+    fn unit_cell(unit: usize, k: usize) -> (usize, usize) {
+        let cell = Sudoku::<N, BR, BC>::unit(unit)[k] as usize;
+        (cell / N, cell % N)
+    }
+
+    fn unit_placed(&self, unit: usize) -> u16 {
+        match unit / N {
+            0 => self.constraints.row[unit],
+            1 => self.constraints.col[unit - N],
+            _ => self.constraints.s_box[unit - 2 * N],
+        }
+    }
+
+    /// Reverts trail entries until the trail is `len` long.
+    fn undo_to(&mut self, len: usize) {
+        self.nogoods.head = self.nogoods.head.min(len);
+        while self.trail.len() > len {
+            match self.trail.pop() {
+                Some(TrailEntry::Assign { row, col }) => {
+                    let val = self.grid[row][col];
+                    self.constraints.unmark_taken(row, col, val);
+                    self.grid[row][col] = 0;
+                }
+                Some(TrailEntry::Ban { row, col, prev }) => {
+                    self.constraints.unban(row, col, prev);
+                }
+                None => break,
+            }
+        }
     }
 
     /// Propagates singleton candidates (cells with only one possible value).
     /// Returns Err(SolveError::Unsolvable) if any cell has no candidates.
-    fn singleton_propagation(&mut self) -> Result<(), SolveError> {
-        // Preallocate worklist to reduce reallocations (max 81 cells)
-        let mut queue: VecDeque<(usize, usize)> = VecDeque::with_capacity(N * N);
+    /// `queue` is a scratch worklist owned by the solver, so it is reused between calls.
+    fn singleton_propagation(
+        &mut self,
+        queue: &mut VecDeque<(usize, usize)>,
+    ) -> Result<(), SolveError> {
+        queue.clear();
 
         // Seed with initial naked singles
-        for r in 0..N {
-            for c in 0..N {
-                if self.grid[r][c] == 0 {
-                    // let taken = self.constraints.forbidden_candidates(r, c);
-                    let avail_count =
-                        self.constraints.available_candidates(r, c).count_ones() as u8;
-                    if avail_count == 0 {
-                        return Err(SolveError::Unsolvable);
-                    } else if avail_count == 1 {
-                        queue.push_back((r, c));
-                    }
+        for cell in 0..N * N {
+            if self.constraints.open[cell] != 0 {
+                let avail_count = self.constraints.cand[cell].count_ones();
+                if avail_count == 0 {
+                    self.conflict = Some(Conflict::Cell(cell / N, cell % N));
+                    return Err(SolveError::Unsolvable);
+                } else if avail_count == 1 {
+                    queue.push_back((cell / N, cell % N));
                 }
             }
         }
@@ -137,182 +537,561 @@ impl<const N: usize, const BR: usize, const BC: usize> DfsNode<N, BR, BC> {
             }
 
             let k = (domain_bits.trailing_zeros() + 1) as u8;
-            self.grid[r][c] = k;
-            self.constraints.mark_taken(r, c, k);
-
-            // Enqueue peers that became singles
-            for i in 0..N {
-                if i != r && self.grid[i][c] == 0 {
-                    // domain_bits = FULL_MASK_N & !self.constraints.forbidden_candidates(i, c);
-                    if self.constraints.available_candidates(i, c).count_ones() == 1 {
-                        queue.push_back((i, c));
+            let mut singles = self.assign(r, c, k, Reason::NakedSingle);
+
+            // Enqueue peers that became singles, older ones are queued already
+            let peers = Sudoku::<N, BR, BC>::peers(r * N + c);
+            while singles != 0 {
+                let p = peers[singles.trailing_zeros() as usize] as usize;
+                queue.push_back((p / N, p % N));
+                singles &= singles - 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs propagation up to `level` until nothing changes.
+    /// Returns Err(SolveError::Unsolvable) on a contradiction.
+    fn propagate(
+        &mut self,
+        level: Propagation,
+        queue: &mut VecDeque<(usize, usize)>,
+    ) -> Result<(), SolveError> {
+        loop {
+            self.singleton_propagation(queue)?;
+            if self.propagate_nogoods()? {
+                continue;
+            }
+            if level >= Propagation::HiddenSingles && self.hidden_singles()? {
+                continue;
+            }
+            if level >= Propagation::LockedCandidates && self.locked_candidates() {
+                continue;
+            }
+            return Ok(());
+        }
+    }
+
+    /// Places every value that has a single possible cell in some unit.
+    /// Returns Ok(true) if anything was placed, or Err(SolveError::Unsolvable) if a value has no cell left.
+    fn hidden_singles(&mut self) -> Result<bool, SolveError> {
+        let mut placed_any = false;
+
+        for unit in 0..3 * N {
+            // once - seen in at least one cell, twice - seen in at least two cells
+            let (mut once, mut twice) = (0u16, 0u16);
+            for k in 0..N {
+                let (r, c) = Self::unit_cell(unit, k);
+                if self.grid[r][c] == 0 {
+                    let m = self.constraints.available_candidates(r, c);
+                    twice |= once & m;
+                    once |= m;
+                }
+            }
+            let missing = Constraints::<N, BR, BC>::FULL_MASK_N & !self.unit_placed(unit) & !once;
+            if missing != 0 {
+                let val = (missing.trailing_zeros() + 1) as u8;
+                self.conflict = Some(Conflict::Unit { unit, val });
+                return Err(SolveError::Unsolvable);
+            }
+
+            let mut singles = once & !twice;
+            while singles != 0 {
+                let bit = singles & singles.wrapping_neg();
+                let cell = (0..N).map(|k| Self::unit_cell(unit, k)).find(|&(r, c)| {
+                    self.grid[r][c] == 0 && self.constraints.available_candidates(r, c) & bit != 0
+                });
+                // an earlier single of this unit took the only cell
+                let val = (bit.trailing_zeros() + 1) as u8;
+                let Some((r, c)) = cell else {
+                    self.conflict = Some(Conflict::Unit { unit, val });
+                    return Err(SolveError::Unsolvable);
+                };
+                self.assign(r, c, val, Reason::HiddenSingle(unit));
+                placed_any = true;
+                singles ^= bit;
+            }
+        }
+
+        Ok(placed_any)
+    }
+
+    /// Pointing and claiming: a value whose candidates in a box all lie on one line is
+    /// removed from the rest of that line, and a value whose candidates on a line all lie
+    /// in one box is removed from the rest of that box.
+    /// Returns true if any candidate was eliminated.
+    fn locked_candidates(&mut self) -> bool {
+        let mut changed = false;
+
+        for b in 0..N {
+            let box_unit = 2 * N + b;
+            let (r0, c0) = Self::unit_cell(box_unit, 0);
+            let lines = (r0..r0 + BR).chain(N + c0..N + c0 + BC);
+            for line in lines {
+                let in_box = |r: usize, c: usize| Sudoku::<N, BR, BC>::box_index(r, c) == b;
+                let on_line = |r: usize, c: usize| {
+                    if line < N { r == line } else { c == line - N }
+                };
+
+                let (mut inter, mut box_rest, mut line_rest) = (0u16, 0u16, 0u16);
+                for k in 0..N {
+                    let (r, c) = Self::unit_cell(box_unit, k);
+                    if self.grid[r][c] == 0 {
+                        let m = self.constraints.available_candidates(r, c);
+                        if on_line(r, c) {
+                            inter |= m;
+                        } else {
+                            box_rest |= m;
+                        }
+                    }
+                    let (r, c) = Self::unit_cell(line, k);
+                    if self.grid[r][c] == 0 && !in_box(r, c) {
+                        line_rest |= self.constraints.available_candidates(r, c);
                     }
                 }
-                if i != c && self.grid[r][i] == 0 {
-                    // domain_bits = FULL_MASK_N & !self.constraints.forbidden_candidates(r, i);
-                    if self.constraints.available_candidates(r, i).count_ones() == 1 {
-                        queue.push_back((r, i));
+
+                let pointing = inter & !box_rest & line_rest;
+                let claiming = inter & !line_rest & box_rest;
+                for k in 0..N {
+                    if pointing != 0 {
+                        let (r, c) = Self::unit_cell(line, k);
+                        if !in_box(r, c) {
+                            changed |= self.eliminate(r, c, pointing, Reason::Pointing { b, line });
+                        }
+                    }
+                    if claiming != 0 {
+                        let (r, c) = Self::unit_cell(box_unit, k);
+                        if !on_line(r, c) {
+                            changed |= self.eliminate(r, c, claiming, Reason::Claiming { b, line });
+                        }
                     }
                 }
             }
-            // Box (exclude row/col to avoid duplicates)
-            let (br, bc) = Sudoku::<N, BR, BC>::box_coord(r, c);
-            for dr in 0..BR {
-                for dc in 0..BC {
-                    let rr = br + dr;
-                    let cc = bc + dc;
-                    if (rr != r) && (cc != c) && self.grid[rr][cc] == 0 {
-                        // domain_bits = FULL_MASK_N & !self.constraints.forbidden_candidates(rr, cc);
-                        if self.constraints.available_candidates(rr, cc).count_ones() == 1 {
-                            queue.push_back((rr, cc));
+        }
+
+        changed
+    }
+
+    fn add_nogood(&mut self, lits: Vec<u32>) -> usize {
+        let id = self.nogoods.lits.len();
+        if lits.len() == 1 {
+            self.nogoods.units.push(id);
+        } else {
+            self.nogoods.watches[lits[0] as usize].push(id);
+            self.nogoods.watches[lits[1] as usize].push(id);
+        }
+        self.nogoods.lits.push(lits);
+        id
+    }
+
+    fn lit_is_true(&self, lit: u32) -> bool {
+        let cell = lit as usize / N;
+        self.grid[cell / N][cell % N] as usize == lit as usize % N + 1
+    }
+
+    /// Bans the last unassigned literal of every nogood whose other literals are all assigned.
+    /// Returns Ok(true) if a candidate was banned, or Err(SolveError::Unsolvable) if a nogood holds entirely.
+    fn propagate_nogoods(&mut self) -> Result<bool, SolveError> {
+        if self.nogoods.lits.is_empty() {
+            self.nogoods.head = self.trail.len();
+            return Ok(false);
+        }
+        let mut changed = false;
+
+        // backjumps and restarts to the top undo these bans, so they are checked every time
+        for i in 0..self.nogoods.units.len() {
+            let id = self.nogoods.units[i];
+            let lit = self.nogoods.lits[id][0];
+            let cell = lit as usize / N;
+            if self.lit_is_true(lit) {
+                self.conflict = Some(Conflict::Nogood(id));
+                return Err(SolveError::Unsolvable);
+            }
+            let mask = 1u16 << (lit as usize % N);
+            changed |= self.eliminate(cell / N, cell % N, mask, Reason::Nogood(id));
+        }
+
+        while self.nogoods.head < self.trail.len() {
+            let TrailEntry::Assign { row, col } = self.trail[self.nogoods.head] else {
+                self.nogoods.head += 1;
+                continue;
+            };
+            self.nogoods.head += 1;
+            let lit = ((row * N + col) * N + self.grid[row][col] as usize - 1) as u32;
+
+            let mut watching = std::mem::take(&mut self.nogoods.watches[lit as usize]);
+            let mut i = 0;
+            while i < watching.len() {
+                let id = watching[i];
+                let lits = &mut self.nogoods.lits[id];
+                if lits[0] == lit {
+                    lits.swap(0, 1);
+                }
+                // lits[1] is the literal just assigned, move its watch to one that is not
+                let grid = &self.grid;
+                let free = (2..lits.len()).find(|&k| {
+                    let cell = lits[k] as usize / N;
+                    grid[cell / N][cell % N] as usize != lits[k] as usize % N + 1
+                });
+                if let Some(k) = free {
+                    lits.swap(1, k);
+                    self.nogoods.watches[lits[1] as usize].push(id);
+                    watching.swap_remove(i);
+                    continue;
+                }
+
+                let last = lits[0];
+                if self.lit_is_true(last) {
+                    self.conflict = Some(Conflict::Nogood(id));
+                    self.nogoods.watches[lit as usize] = watching;
+                    return Err(SolveError::Unsolvable);
+                }
+                let cell = last as usize / N;
+                let mask = 1u16 << (last as usize % N);
+                changed |= self.eliminate(cell / N, cell % N, mask, Reason::Nogood(id));
+                i += 1;
+            }
+            self.nogoods.watches[lit as usize] = watching;
+        }
+
+        Ok(changed)
+    }
+
+    /// Resolves the current conflict back to the decisions it follows from, pushing their
+    /// literals to `out`. Each fact is explained by facts with smaller trail stamps only,
+    /// so the explanation never goes in circles.
+    fn analyze(&self, conflict: Conflict, out: &mut Vec<u32>) {
+        let mut work = Vec::new();
+        match conflict {
+            Conflict::Cell(row, col) => {
+                work.extend((1..=N as u8).map(|v| Cause::Elim(row * N + col, v)));
+            }
+            Conflict::Unit { unit, val } => {
+                for k in 0..N {
+                    let (r, c) = Self::unit_cell(unit, k);
+                    work.push(self.unit_cause(r * N + c, val, u32::MAX));
+                }
+            }
+            Conflict::Nogood(id) => {
+                work.extend(
+                    self.nogoods.lits[id]
+                        .iter()
+                        .map(|&l| Cause::Assign(l as usize / N)),
+                );
+            }
+        }
+
+        let mut seen_assign = vec![false; N * N];
+        let mut seen_elim = vec![0u16; N * N];
+        while let Some(cause) = work.pop() {
+            match cause {
+                Cause::Assign(cell) => {
+                    if std::mem::replace(&mut seen_assign[cell], true) {
+                        continue;
+                    }
+                    let val = self.grid[cell / N][cell % N];
+                    let (stamp, reason) = self.assigned_at[cell];
+                    match reason {
+                        Reason::Given => {}
+                        Reason::Decision => out.push((cell * N + val as usize - 1) as u32),
+                        Reason::NakedSingle => work.extend(
+                            (1..=N as u8)
+                                .filter(|&v| v != val)
+                                .map(|v| Cause::Elim(cell, v)),
+                        ),
+                        Reason::HiddenSingle(unit) => {
+                            for k in 0..N {
+                                let (r, c) = Self::unit_cell(unit, k);
+                                if r * N + c != cell {
+                                    work.push(self.unit_cause(r * N + c, val, stamp));
+                                }
+                            }
                         }
+                        _ => unreachable!("not a reason for a value"),
                     }
                 }
+                Cause::Elim(cell, val) => {
+                    let bit = 1u16 << (val - 1);
+                    if seen_elim[cell] & bit != 0 {
+                        continue;
+                    }
+                    seen_elim[cell] |= bit;
+                    self.explain_elim(cell, val, &mut work);
+                }
             }
         }
+    }
 
-        Ok(())
+    /// Why an empty cell lacks `val` at time `stamp`: a unit peer held another value by then
+    /// or the cell itself had lost the candidate.
+    fn unit_cause(&self, cell: usize, val: u8, stamp: u32) -> Cause {
+        if self.grid[cell / N][cell % N] != 0 && self.assigned_at[cell].0 < stamp {
+            Cause::Assign(cell)
+        } else {
+            Cause::Elim(cell, val)
+        }
     }
 
-    /// Finds the cell with minimum remaining values (MRV).
-    /// Returns Ok(MrvRes::Cell(i, j))) for a cell to fill, or Ok(MrvRes::Solved) if solved, or Err(SolveError::Unsolvable).
-    fn mrv(&mut self) -> Result<MrvRes, SolveError> {
-        self.singleton_propagation()?;
+    /// Explains a missing candidate by its earliest cause: a peer holding the value or a ban.
+    fn explain_elim(&self, cell: usize, val: u8, work: &mut Vec<Cause>) {
+        let (row, col) = (cell / N, cell % N);
+        let bit = 1u16 << (val - 1);
+        let units = [
+            row,
+            N + col,
+            2 * N + Sudoku::<N, BR, BC>::box_index(row, col),
+        ];
+        let holder = units
+            .into_iter()
+            .filter(|&u| self.unit_placed(u) & bit != 0)
+            .filter_map(|u| {
+                (0..N)
+                    .map(|k| Self::unit_cell(u, k))
+                    .find(|&(r, c)| self.grid[r][c] == val)
+                    .map(|(r, c)| r * N + c)
+            })
+            .min_by_key(|&h| self.assigned_at[h].0);
+        let ban = (self.constraints.banned[row][col] & bit != 0)
+            .then(|| self.banned_at[cell * N + val as usize - 1]);
+
+        let (stamp, reason) = match (holder, ban) {
+            (Some(h), Some((stamp, _))) if self.assigned_at[h].0 < stamp => {
+                work.push(Cause::Assign(h));
+                return;
+            }
+            (_, Some(ban)) => ban,
+            (Some(h), None) => {
+                work.push(Cause::Assign(h));
+                return;
+            }
+            (None, None) => unreachable!("candidate is available"),
+        };
+        match reason {
+            Reason::Nogood(id) => {
+                let lit = (cell * N + val as usize - 1) as u32;
+                work.extend(
+                    self.nogoods.lits[id]
+                        .iter()
+                        .filter(|&&l| l != lit)
+                        .map(|&l| Cause::Assign(l as usize / N)),
+                );
+            }
+            Reason::Pointing { b, line } | Reason::Claiming { b, line } => {
+                let pointing = matches!(reason, Reason::Pointing { .. });
+                let on_line = |r: usize, c: usize| if line < N { r == line } else { c == line - N };
+                // pointing: the rest of the box lacks the value, claiming: the rest of the line
+                let unit = if pointing { 2 * N + b } else { line };
+                for k in 0..N {
+                    let (r, c) = Self::unit_cell(unit, k);
+                    let outside = if pointing {
+                        !on_line(r, c)
+                    } else {
+                        Sudoku::<N, BR, BC>::box_index(r, c) != b
+                    };
+                    if outside {
+                        work.push(self.unit_cause(r * N + c, val, stamp));
+                    }
+                }
+            }
+            // ruled out by the caller's candidate masks
+            Reason::Given => {}
+            _ => unreachable!("chronological bans are not made when learning"),
+        }
+    }
 
-        let mut target_cell: (usize, usize) = (0, 0);
+    /// Picks the cell to branch on according to `order`, e.g. the one with minimum remaining values (MRV).
+    /// With `random_ties` cells the order ranks equally are chosen between uniformly at random.
+    /// Returns Ok(MrvRes::Cell(i, j))) for a cell to fill, or Ok(MrvRes::Solved) if solved, or Err(SolveError::Unsolvable).
+    fn mrv(
+        &mut self,
+        order: VarOrder,
+        random_ties: bool,
+        weights: &[u32],
+        rng: &mut SmallRng,
+    ) -> Result<MrvRes, SolveError> {
+        let mut target_cell: Option<(usize, usize)> = None;
         let mut target_cell_domains_count = u8::MAX;
         let mut target_cell_peers_stat = PeerStat {
             peers_count: 0,
             peers_domains_sum: 0,
         };
+        let mut target_cell_wdeg = 0u32;
+        let mut empty_cells = 0u32;
+        let mut ties = 0u32;
+
+        // one pass over the candidate array finds the smallest domain first
+        let min_count = self.constraints.min_open_count();
+        if min_count == u16::MAX {
+            return Ok(MrvRes::Solved);
+        }
+        // propagation can empty a domain it did not enqueue
+        if min_count == 0 {
+            let cell = (0..N * N)
+                .find(|&i| self.constraints.open[i] != 0 && self.constraints.cand[i] == 0)
+                .expect("an empty cell has no candidates");
+            self.conflict = Some(Conflict::Cell(cell / N, cell % N));
+            return Err(SolveError::Unsolvable);
+        }
+        // without random ties only cells of the smallest domain can be chosen by count
+        let min_only = !random_ties && matches!(order, VarOrder::Mrv | VarOrder::MrvDegree);
 
         for row in 0..N {
             for col in 0..N {
                 if self.grid[row][col] > 0 {
                     continue;
                 }
-                // let current_cell_domain_bits =
-                // FULL_MASK_N & !self.constraints.forbidden_candidates(row, col);
                 let current_cell_domains_count =
                     self.constraints.available_candidates(row, col).count_ones() as u8;
-                if current_cell_domains_count < target_cell_domains_count {
+                if min_only && current_cell_domains_count as u16 != min_count {
+                    continue;
+                }
+                // the first cell of the smallest domain is the plain MRV choice
+                if min_only && order == VarOrder::Mrv {
+                    return Ok(MrvRes::Cell(row, col));
+                }
+                empty_cells += 1;
+
+                let mut current_cell_peers_stat = None;
+                let rank = match order {
+                    VarOrder::Natural => Ordering::Greater,
+                    // reservoir sampling over the empty cells
+                    VarOrder::Random if rng.random_range(0..empty_cells) == 0 => Ordering::Less,
+                    VarOrder::Random => Ordering::Greater,
+                    VarOrder::Mrv => current_cell_domains_count.cmp(&target_cell_domains_count),
+                    VarOrder::MrvDegree => current_cell_domains_count
+                        .cmp(&target_cell_domains_count)
+                        .then_with(|| {
+                            // the idea of this tie break is to cut of maximum wrong choice branches
+                            let stat = self.peers_stat(row, col);
+                            let rank = target_cell_peers_stat
+                                .peers_count
+                                .cmp(&stat.peers_count)
+                                .then(
+                                    stat.peers_domains_sum
+                                        .cmp(&target_cell_peers_stat.peers_domains_sum),
+                                );
+                            current_cell_peers_stat = Some(stat);
+                            rank
+                        }),
+                    // dom / wdeg against target_dom / target_wdeg
+                    VarOrder::DomWdeg => (current_cell_domains_count as u32 * target_cell_wdeg)
+                        .cmp(&(target_cell_domains_count as u32 * Self::wdeg(weights, row, col))),
+                };
+
+                let better = target_cell.is_none()
+                    || match rank {
+                        Ordering::Less => true,
+                        Ordering::Equal if random_ties => {
+                            ties += 1;
+                            rng.random_range(0..ties) == 0
+                        }
+                        _ => false,
+                    };
+
+                if better {
+                    if rank != Ordering::Equal {
+                        ties = 1;
+                    }
+                    target_cell = Some((row, col));
                     target_cell_domains_count = current_cell_domains_count;
-                    target_cell = (row, col);
-                    target_cell_peers_stat = self.peers_stat(target_cell.0, target_cell.1);
-                } else if target_cell_domains_count == current_cell_domains_count {
-                    // the idea of this tie break is to cut of maximum wrong choice branches
-                    let current_cell_peers_stat = self.peers_stat(row, col);
-                    if target_cell_peers_stat.peers_count < current_cell_peers_stat.peers_count
-                        || (target_cell_peers_stat.peers_count
-                            == current_cell_peers_stat.peers_count
-                            && target_cell_peers_stat.peers_domains_sum
-                                > current_cell_peers_stat.peers_domains_sum)
-                    {
-                        target_cell = (row, col);
-                        target_cell_peers_stat = current_cell_peers_stat;
+                    match order {
+                        VarOrder::MrvDegree => {
+                            target_cell_peers_stat =
+                                current_cell_peers_stat.unwrap_or_else(|| self.peers_stat(row, col))
+                        }
+                        VarOrder::DomWdeg => target_cell_wdeg = Self::wdeg(weights, row, col),
+                        _ => {}
                     }
                 }
             }
         }
 
-        if target_cell_domains_count == u8::MAX {
-            return Ok(MrvRes::Solved);
-        }
+        Ok(match target_cell {
+            Some((row, col)) => MrvRes::Cell(row, col),
+            None => MrvRes::Solved,
+        })
+    }
 
-        Ok(MrvRes::Cell(target_cell.0, target_cell.1))
+    /// Summed weight of the row, column and box of a cell.
+    fn wdeg(weights: &[u32], row: usize, col: usize) -> u32 {
+        weights[row] + weights[N + col] + weights[2 * N + Sudoku::<N, BR, BC>::box_index(row, col)]
     }
 
     // peers_count - the more empty neighbors, the sooner dead-end branches will be cut off
     // sum_peers_domains - the fewer domains peers have, the higher the chance that substitution will remove dead-end branches from other zero cells
-    fn peers_stat(&mut self, row: usize, col: usize) -> PeerStat {
+    fn peers_stat(&self, row: usize, col: usize) -> PeerStat {
         let mut res = PeerStat {
             peers_count: 0,
             peers_domains_sum: 0,
         };
 
-        for i in 0..N {
-            if i != row && self.grid[i][col] == 0 {
-                // let domain_bits = FULL_MASK_N & !self.constraints.forbidden_candidates(i, col);
+        for &p in Sudoku::<N, BR, BC>::peers(row * N + col) {
+            if self.constraints.open[p as usize] != 0 {
                 res.peers_count += 1;
-                res.peers_domains_sum +=
-                    self.constraints.available_candidates(i, col).count_ones() as u16;
+                res.peers_domains_sum += self.constraints.cand[p as usize].count_ones() as u16;
             }
+        }
 
-            if i != col && self.grid[row][i] == 0 {
-                // let domain_bits = FULL_MASK_N & !self.constraints.forbidden_candidates(row, i);
-                res.peers_count += 1;
-                res.peers_domains_sum +=
-                    self.constraints.available_candidates(row, i).count_ones() as u16;
-            }
-        }
-
-        let (box_row_start, box_col_start) = Sudoku::<N, BR, BC>::box_coord(row, col);
-        for b_row in 0..BR {
-            for b_col in 0..BC {
-                if box_row_start + b_row != row
-                    && box_col_start + b_col != col
-                    && self.grid[box_row_start + b_row][box_col_start + b_col] == 0
-                {
-                    // let domain_bits = FULL_MASK_N
-                    // & !self
-                    // .constraints
-                    // .forbidden_candidates(box_row_start + b_row, box_col_start + b_col);
-
-                    res.peers_count += 1;
-                    res.peers_domains_sum += self
-                        .constraints
-                        .available_candidates(box_row_start + b_row, box_col_start + b_col)
-                        .count_ones() as u16;
+        res
+    }
+
+    /// Picks the candidate of an empty cell to try first according to `order`.
+    fn choose_value(
+        &self,
+        row: usize,
+        col: usize,
+        order: ValueOrder,
+        random_ties: bool,
+        rng: &mut SmallRng,
+    ) -> u8 {
+        let avail = self.constraints.available_candidates(row, col);
+        match order {
+            ValueOrder::Natural => (avail.trailing_zeros() + 1) as u8,
+            ValueOrder::Lcv => self.lcv(row, col, random_ties.then_some(rng)),
+            ValueOrder::Random => {
+                let mut bits = avail;
+                for _ in 0..rng.random_range(0..avail.count_ones()) {
+                    bits &= bits - 1; // drop lsb
                 }
+                (bits.trailing_zeros() + 1) as u8
             }
         }
-
-        res
     }
 
     // Least Constraining Value
     // select the candidate who is least likely affects the peers - this way we reduce the probability of a dead-end
-    fn lcv(&mut self, row: usize, col: usize) -> u8 {
+    // with `tie_rng` equally scored candidates are chosen between uniformly at random
+    fn lcv(&self, row: usize, col: usize, mut tie_rng: Option<&mut SmallRng>) -> u8 {
         let mut max_score: u8 = 0;
         let mut val = 0;
+        let mut ties = 0u32;
 
         let mut avail_can_bits = self.constraints.available_candidates(row, col);
         while avail_can_bits > 0 {
             let lsb = avail_can_bits & (!avail_can_bits + 1);
-            let mut score = 0;
-            for l in 0..N {
-                if l != col
-                    && self.grid[row][l] == 0
-                    && self.constraints.forbidden_candidates(row, l) & lsb != 0
-                {
-                    score += 1;
-                }
-                if l != row
-                    && self.grid[l][col] == 0
-                    && self.constraints.forbidden_candidates(l, col) & lsb != 0
-                {
-                    score += 1;
-                }
-            }
+            // empty peers that cannot take the value anyway
+            let score = Sudoku::<N, BR, BC>::peers(row * N + col)
+                .iter()
+                .filter(|&&p| {
+                    self.constraints.open[p as usize] != 0
+                        && self.constraints.cand[p as usize] & lsb == 0
+                })
+                .count() as u8;
 
-            let (box_row_start, box_col_start) = Sudoku::<N, BR, BC>::box_coord(row, col);
-            for b_row in 0..BR {
-                for b_col in 0..BC {
-                    if box_row_start + b_row != row
-                        && box_col_start + b_col != col
-                        && self.grid[box_row_start + b_row][box_col_start + b_col] == 0
-                        && self
-                            .constraints
-                            .forbidden_candidates(box_row_start + b_row, box_col_start + b_col)
-                            & lsb
-                            != 0
-                    {
-                        score += 1;
-                    }
+            let better = match (score.cmp(&max_score), tie_rng.as_deref_mut()) {
+                (Ordering::Greater, _) => {
+                    ties = 1;
+                    true
                 }
-            }
-
-            if score >= max_score {
+                (Ordering::Equal, Some(rng)) => {
+                    ties += 1;
+                    rng.random_range(0..ties) == 0
+                }
+                (Ordering::Equal, None) => true,
+                (Ordering::Less, _) => false,
+            };
+            if better {
                 max_score = score;
                 val = lsb.trailing_zeros() + 1;
             }
@@ -324,40 +1103,540 @@ impl<const N: usize, const BR: usize, const BC: usize> DfsNode<N, BR, BC> {
     }
 }
 
-pub struct DfsBacktracking<const N: usize, const BR: usize, const BC: usize>;
+/// Depth-first search over a single mutable state with an undo trail.
+///
+/// A decision pushes its trail position; a failure undoes the trail back to that
+/// position and bans the failed value, so the ban lives exactly as long as the
+/// parent decision does. All buffers are kept between `solve` calls.
+///
+/// With `DfsConfig::learning` the failed branch is instead analysed down to the
+/// decisions that caused it, and the nogood they form is kept for the rest of the search.
+pub struct DfsBacktracking<const N: usize, const BR: usize, const BC: usize> {
+    config: DfsConfig,
+    state: SearchState<N, BR, BC>,
+    decisions: Vec<Decision>,
+    queue: VecDeque<(usize, usize)>,
+    /// Failure counts of rows, columns and boxes (unit indexes as in `unit_cell`), for dom/wdeg.
+    weights: Vec<u32>,
+    rng: SmallRng,
+    stats: SearchStats,
+    cancel: CancelToken,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Default for DfsBacktracking<N, BR, BC> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl<const N: usize, const BR: usize, const BC: usize> DfsBacktracking<N, BR, BC> {
+    pub fn new() -> Self {
+        Self::with_config(DfsConfig::default())
+    }
+
+    pub fn with_config(config: DfsConfig) -> Self {
+        Self {
+            config,
+            state: SearchState::new(),
+            decisions: Vec::with_capacity(N * N),
+            queue: VecDeque::with_capacity(N * N),
+            weights: vec![1; 3 * N],
+            rng: SmallRng::seed_from_u64(config.seed),
+            stats: SearchStats::default(),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    pub fn stats(&self) -> SearchStats {
+        self.stats
+    }
+
+    pub fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    fn bump_weights(&mut self) {
+        match self.state.conflict.take() {
+            Some(Conflict::Cell(row, col)) => {
+                self.weights[row] += 1;
+                self.weights[N + col] += 1;
+                self.weights[2 * N + Sudoku::<N, BR, BC>::box_index(row, col)] += 1;
+            }
+            Some(Conflict::Unit { unit, .. }) => self.weights[unit] += 1,
+            Some(Conflict::Nogood(_)) | None => {}
+        }
+    }
+
     pub fn solve(&mut self, s: &mut Sudoku<N, BR, BC>) -> Result<(), SolveError> {
-        let mut dfs_stack = vec![DfsNode::<N, BR, BC>::new(s.init)];
+        match self.search(s, 1)? {
+            0 => Err(SolveError::Unsolvable),
+            _ => Ok(()),
+        }
+    }
+
+    /// Propagates the givens and candidate masks of `s` without guessing, at least up to
+    /// hidden singles, and returns the contradiction it runs into. `None` does not promise
+    /// a solution, only `solve` decides that.
+    /// Returns Err(SolveError::InvalidPuzzle) for the same inputs `solve` rejects.
+    pub fn contradiction(
+        &mut self,
+        s: &Sudoku<N, BR, BC>,
+    ) -> Result<Option<Contradiction>, SolveError> {
+        self.state.reset(s)?;
+        let level = self.config.propagation.max(Propagation::HiddenSingles);
+        let outcome = self.state.propagate(level, &mut self.queue).and_then(|_| {
+            self.state
+                .mrv(VarOrder::Natural, false, &self.weights, &mut self.rng)
+        });
+        Ok(match (outcome, self.state.conflict) {
+            (Err(_), Some(Conflict::Cell(row, col))) => {
+                Some(Contradiction::NoCandidates { row, col })
+            }
+            (Err(_), Some(Conflict::Unit { unit, val })) => {
+                Some(Contradiction::NoPlace { unit, val })
+            }
+            _ => None,
+        })
+    }
+
+    /// Counts the solutions of `s`, stopping once `limit` of them are found.
+    /// The first solution found is stored in `s.solution`.
+    pub fn count_solutions(
+        &mut self,
+        s: &mut Sudoku<N, BR, BC>,
+        limit: u64,
+    ) -> Result<u64, SolveError> {
+        self.search(s, limit.max(1))
+    }
+
+    fn search(&mut self, s: &mut Sudoku<N, BR, BC>, limit: u64) -> Result<u64, SolveError> {
+        self.state.reset(s)?;
+        self.decisions.clear();
+        self.weights.fill(1);
+        self.rng = SmallRng::seed_from_u64(self.config.seed);
+        self.stats = SearchStats::default();
+        let config = self.config;
+        let mut cutoff = config.restarts.cutoff(0);
+        let mut run_backtracks = 0u64;
+        let mut found = 0u64;
+
         loop {
-            let top_node = dfs_stack.last_mut().ok_or(SolveError::Unsolvable)?;
-            let new_node = match top_node.mrv() {
-                Ok(mrv) => match mrv {
-                    MrvRes::Cell(row, col) => {
-                        let lcv = top_node.lcv(row, col);
-                        top_node.fork(row, col, lcv)
+            if self.cancel.is_cancelled() {
+                return Err(SolveError::Cancelled);
+            }
+            let next = self
+                .state
+                .propagate(config.propagation, &mut self.queue)
+                .and_then(|_| {
+                    self.state.mrv(
+                        config.var_order,
+                        config.random_ties,
+                        &self.weights,
+                        &mut self.rng,
+                    )
+                });
+            match next {
+                Ok(MrvRes::Cell(row, col)) => {
+                    let val = self.state.choose_value(
+                        row,
+                        col,
+                        config.value_order,
+                        config.random_ties,
+                        &mut self.rng,
+                    );
+                    self.decisions.push(Decision {
+                        row,
+                        col,
+                        val,
+                        trail_len: self.state.trail.len(),
+                    });
+                    self.state.assign(row, col, val, Reason::Decision);
+                    self.stats.decisions += 1;
+                }
+                Ok(MrvRes::Solved) => {
+                    if found == 0 {
+                        s.solution = self.state.grid;
                     }
-                    MrvRes::Solved => {
-                        s.solution = top_node.grid;
-                        return Ok(());
+                    found += 1;
+                    if found >= limit || !self.backtrack() {
+                        return Ok(found);
                     }
-                },
+                }
                 Err(_) => {
-                    let wrong_node = dfs_stack
-                        .pop()
-                        .ok_or(SolveError::Unsolvable)?
-                        .choice
-                        .ok_or(SolveError::Unsolvable)?;
-                    let top_node = dfs_stack.last_mut().ok_or(SolveError::Unsolvable)?;
-                    top_node.constraints.forbid_cell_val(
-                        wrong_node.row,
-                        wrong_node.col,
-                        wrong_node.val,
-                    );
-                    continue;
+                    if !self.backtrack() {
+                        return Ok(found);
+                    }
+                    self.stats.backtracks += 1;
+
+                    run_backtracks += 1;
+                    // without nogoods a restart would find the same solutions again
+                    let can_restart = config.learning || found == 0;
+                    if can_restart && cutoff.is_some_and(|limit| run_backtracks >= limit) {
+                        // keep weights and the generator state, so the next run branches differently
+                        self.state.undo_to(0);
+                        self.decisions.clear();
+                        self.stats.restarts += 1;
+                        run_backtracks = 0;
+                        cutoff = config.restarts.cutoff(self.stats.restarts);
+                    }
                 }
+            }
+        }
+    }
+
+    /// Leaves the current branch after a conflict or a solution: chronologically by
+    /// banning the last decision, or with learning by recording a nogood of the decisions
+    /// behind the conflict (all of them for a solution) and jumping back to where it bans something.
+    /// Returns false if there is nothing left to search.
+    fn backtrack(&mut self) -> bool {
+        if !self.config.learning {
+            self.bump_weights();
+            let Some(wrong) = self.decisions.pop() else {
+                return false;
             };
-            dfs_stack.push(new_node);
+            self.state.undo_to(wrong.trail_len);
+            self.state
+                .ban(wrong.row, wrong.col, wrong.val, Reason::Search);
+            return true;
+        }
+
+        let mut lits = Vec::new();
+        match self.state.conflict {
+            Some(conflict) => self.state.analyze(conflict, &mut lits),
+            None => lits.extend(
+                self.decisions
+                    .iter()
+                    .map(|d| ((d.row * N + d.col) * N + d.val as usize - 1) as u32),
+            ),
         }
+        self.bump_weights();
+        if lits.is_empty() {
+            return false;
+        }
+
+        // decision levels are positions in `decisions` plus one, the highest goes first
+        let level = |lit: u32| {
+            let cell = lit as usize / N;
+            self.decisions
+                .iter()
+                .position(|d| d.row * N + d.col == cell)
+                .map_or(0, |i| i + 1)
+        };
+        lits.sort_by_cached_key(|&l| std::cmp::Reverse(level(l)));
+        let back = lits.get(1).map_or(0, |&l| level(l));
+
+        self.state.undo_to(self.decisions[back].trail_len);
+        self.decisions.truncate(back);
+        let cell = lits[0] as usize / N;
+        let val = (lits[0] as usize % N + 1) as u8;
+        let id = self.state.add_nogood(lits);
+        self.state.ban(cell / N, cell % N, val, Reason::Nogood(id));
+        self.stats.learned += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sudoku::Sudoku9,
+        test_util::{HARD, parse9},
+    };
+
+    #[test]
+    fn stronger_propagation_guesses_less() {
+        let levels = [
+            Propagation::NakedSingles,
+            Propagation::HiddenSingles,
+            Propagation::LockedCandidates,
+        ];
+        let mut solutions = Vec::new();
+        let mut decisions = Vec::new();
+        for propagation in levels {
+            let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+                propagation,
+                ..DfsConfig::default()
+            });
+            let mut s = Sudoku9::new(parse9(HARD));
+            assert!(dfs.solve(&mut s).is_ok());
+            assert!(s.check());
+            solutions.push(s.solution);
+            decisions.push(dfs.stats().decisions);
+        }
+
+        assert!(solutions.windows(2).all(|w| w[0] == w[1]));
+        assert!(decisions[1] < decisions[0]);
+        assert!(decisions[2] <= decisions[1]);
+    }
+
+    #[test]
+    fn candidates_follow_the_trail() {
+        // every cached domain matches the one recomputed from the unit masks and bans
+        fn in_sync(st: &SearchState<6, 2, 3>) -> bool {
+            (0..6).all(|r| {
+                (0..6).all(|c| {
+                    let cons = &st.constraints;
+                    let expected = match st.grid[r][c] {
+                        0 => 0b111111 & !(cons.placed(r, c) | cons.banned[r][c]),
+                        _ => 0,
+                    };
+                    cons.available_candidates(r, c) == expected
+                })
+            })
+        }
+
+        let mut init = [[0u8; 6]; 6];
+        init[0][0] = 1;
+        init[3][4] = 2;
+        let mut st = SearchState::<6, 2, 3>::new();
+        st.reset(&Sudoku::new(init)).unwrap();
+        assert!(in_sync(&st));
+        assert_eq!(st.constraints.min_open_count(), 4); // e.g. (3, 0) sees both givens
+
+        st.assign(0, 1, 2, Reason::Decision);
+        st.ban(1, 0, 3, Reason::Search);
+        let mark = st.trail.len();
+        st.assign(1, 2, 3, Reason::Decision);
+        st.ban_mask(5, 5, 0b1100, Reason::Search);
+        st.assign(5, 1, 4, Reason::Decision);
+        assert!(in_sync(&st));
+        st.undo_to(mark);
+        assert!(in_sync(&st));
+        st.undo_to(0);
+        assert!(in_sync(&st));
+        assert_eq!(st.constraints.min_open_count(), 4);
+    }
+
+    #[test]
+    fn pencilmarks_report_contradictions() {
+        let init = parse9(HARD);
+        let mut solved = Sudoku9::new(init);
+        let mut dfs = DfsBacktracking::<9, 3, 3>::new();
+        dfs.solve(&mut solved).unwrap();
+        let sol = solved.solution;
+
+        // the unique solution passes through the marks
+        let mut marks = [[0x1ffu16; 9]; 9];
+        marks[0][1] = 1 << (sol[0][1] - 1);
+        marks[8][8] |= 1 << (sol[8][8] - 1);
+        assert_eq!(
+            dfs.contradiction(&Sudoku9::with_candidates(init, marks)),
+            Ok(None)
+        );
+        let mut s = Sudoku9::with_candidates(init, marks);
+        assert!(dfs.solve(&mut s).is_ok());
+        assert_eq!(s.solution, sol);
+
+        // ruling out the solution's value leaves nothing, also for the learning search
+        marks[0][1] = 0x1ff & !(1 << (sol[0][1] - 1));
+        let mut s = Sudoku9::with_candidates(init, marks);
+        assert_eq!(dfs.solve(&mut s), Err(SolveError::Unsolvable));
+        let mut learning = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+            learning: true,
+            ..DfsConfig::default()
+        });
+        assert_eq!(learning.solve(&mut s), Err(SolveError::Unsolvable));
+
+        marks[0][1] = 0;
+        assert_eq!(
+            dfs.contradiction(&Sudoku9::with_candidates(init, marks)),
+            Ok(Some(Contradiction::NoCandidates { row: 0, col: 1 }))
+        );
+
+        // 9 is marked nowhere in the last column
+        let mut marks = [[0x1ffu16; 9]; 9];
+        for row in &mut marks {
+            row[8] &= !(1 << 8);
+        }
+        assert_eq!(
+            dfs.contradiction(&Sudoku9::with_candidates([[0; 9]; 9], marks)),
+            Ok(Some(Contradiction::NoPlace {
+                unit: 9 + 8,
+                val: 9
+            }))
+        );
+
+        // a given its own mark rules out
+        let mut marks = [[0x1ffu16; 9]; 9];
+        marks[0][0] = 1;
+        assert!(matches!(
+            dfs.solve(&mut Sudoku9::with_candidates(init, marks)),
+            Err(SolveError::InvalidPuzzle(_))
+        ));
+    }
+
+    #[test]
+    fn hidden_singles_detect_contradiction() {
+        // 1 can go nowhere in the top-left box
+        let mut init = [[0u8; 9]; 9];
+        init[0][3] = 1;
+        init[4][1] = 1;
+        init[8][2] = 1;
+        init[1][0] = 2;
+        init[2][0] = 3;
+        init[1][1] = 4;
+        init[2][1] = 5;
+        init[1][2] = 6;
+        init[2][2] = 7;
+
+        let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+            propagation: Propagation::HiddenSingles,
+            ..DfsConfig::default()
+        });
+        let mut s = Sudoku9::new(init);
+        assert_eq!(dfs.solve(&mut s), Err(SolveError::Unsolvable));
+        assert_eq!(dfs.stats().decisions, 0);
+    }
+
+    #[test]
+    fn every_ordering_solves() {
+        let var_orders = [
+            VarOrder::Natural,
+            VarOrder::Mrv,
+            VarOrder::MrvDegree,
+            VarOrder::DomWdeg,
+            VarOrder::Random,
+        ];
+        let value_orders = [ValueOrder::Natural, ValueOrder::Lcv, ValueOrder::Random];
+        for var_order in var_orders {
+            for value_order in value_orders {
+                let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+                    propagation: Propagation::HiddenSingles,
+                    var_order,
+                    value_order,
+                    seed: 7,
+                    ..DfsConfig::default()
+                });
+                let mut s = Sudoku9::new(parse9(HARD));
+                assert!(dfs.solve(&mut s).is_ok());
+                assert!(s.check());
+            }
+        }
+    }
+
+    #[test]
+    fn random_orders_are_reproducible() {
+        let solve_empty = |seed| {
+            let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+                value_order: ValueOrder::Random,
+                seed,
+                ..DfsConfig::default()
+            });
+            let mut s = Sudoku9::new([[0; 9]; 9]);
+            assert!(dfs.solve(&mut s).is_ok());
+            assert!(s.check());
+            s.solution
+        };
+
+        assert_eq!(solve_empty(1), solve_empty(1));
+        assert_ne!(solve_empty(1), solve_empty(2));
+    }
+
+    #[test]
+    fn learning_finds_the_same_solution() {
+        for propagation in [
+            Propagation::NakedSingles,
+            Propagation::HiddenSingles,
+            Propagation::LockedCandidates,
+        ] {
+            let config = DfsConfig {
+                propagation,
+                ..DfsConfig::default()
+            };
+            let mut plain = DfsBacktracking::<9, 3, 3>::with_config(config);
+            let mut learning = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+                learning: true,
+                ..config
+            });
+            let mut s = Sudoku9::new(parse9(HARD));
+            let mut t = Sudoku9::new(parse9(HARD));
+            assert!(plain.solve(&mut s).is_ok());
+            assert!(learning.solve(&mut t).is_ok());
+            assert!(t.check());
+            assert_eq!(s.solution, t.solution);
+            assert!(learning.stats().learned > 0);
+            assert!(learning.stats().backtracks <= plain.stats().backtracks);
+        }
+    }
+
+    #[test]
+    fn learning_keeps_solution_counts() {
+        // without its first given the puzzle has hundreds of solutions
+        let mut init = parse9(HARD);
+        init[0][0] = 0;
+        let mut counts = Vec::new();
+        for learning in [false, true] {
+            for restarts in [Restarts::Never, Restarts::Luby { unit: 4 }] {
+                let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+                    propagation: Propagation::HiddenSingles,
+                    random_ties: true,
+                    learning,
+                    restarts,
+                    ..DfsConfig::default()
+                });
+                let mut s = Sudoku9::new(init);
+                counts.push(dfs.count_solutions(&mut s, u64::MAX).unwrap());
+                assert!(s.check());
+            }
+        }
+        assert!(counts[0] > 1);
+        assert!(counts.windows(2).all(|w| w[0] == w[1]));
+
+        let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+            learning: true,
+            ..DfsConfig::default()
+        });
+        assert_eq!(
+            dfs.count_solutions(&mut Sudoku9::new(parse9(HARD)), 10),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn luby_sequence() {
+        let seq: Vec<u64> = (1..=15).map(luby).collect();
+        assert_eq!(seq, [1, 1, 2, 1, 1, 2, 4, 1, 1, 2, 1, 1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn restarts_keep_search_complete() {
+        for restarts in [
+            Restarts::Luby { unit: 1 },
+            Restarts::Geometric {
+                first: 1,
+                factor: 1.2,
+            },
+        ] {
+            let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+                random_ties: true,
+                restarts,
+                seed: 3,
+                ..DfsConfig::default()
+            });
+            let mut s = Sudoku9::new(parse9(HARD));
+            assert!(dfs.solve(&mut s).is_ok());
+            assert!(s.check());
+            assert!(dfs.stats().restarts > 0);
+        }
+
+        // a factor that does not grow must still raise the cutoff, or a deterministic
+        // order would restart into the same dead end forever
+        let flat = Restarts::Geometric {
+            first: 16,
+            factor: 1.0,
+        };
+        assert_eq!(
+            (0..4).map(|n| flat.cutoff(n)).collect::<Vec<_>>(),
+            [16, 17, 18, 19].map(Some)
+        );
+        let mut dfs = DfsBacktracking::<9, 3, 3>::with_config(DfsConfig {
+            restarts: flat,
+            ..DfsConfig::default()
+        });
+        let mut s = Sudoku9::new(parse9(HARD));
+        assert!(dfs.solve(&mut s).is_ok());
+        assert!(s.check());
+        assert!(dfs.stats().restarts > 0);
     }
 }
@@ -0,0 +1,296 @@
+use crate::{
+    cnf,
+    sat::Cdcl,
+    solver::{CancelToken, Kind, SolveError, SolverEngine},
+    sudoku::Sudoku,
+    transform,
+};
+use std::{any::Any, collections::HashMap};
+
+/// A puzzle whose size and box shape are only known at runtime, e.g. read by OCR.
+/// Cells are stored row-major, `0` for an empty cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynSudoku {
+    n: usize,
+    br: usize,
+    bc: usize,
+    init: Vec<u8>,
+    solution: Vec<u8>,
+}
+
+impl DynSudoku {
+    /// A grid with boxes `br` rows high and `bc` columns wide, so `(br * bc)^2` cells.
+    /// Returns Err(SolveError::InvalidPuzzle) if `init` has a different number of cells.
+    pub fn new(br: usize, bc: usize, init: Vec<u8>) -> Result<Self, SolveError> {
+        // a shape whose cell count overflows matches no `init`
+        let n = br.checked_mul(bc).unwrap_or(0);
+        if n == 0 || n.checked_mul(n) != Some(init.len()) {
+            return Err(SolveError::InvalidPuzzle(
+                "grid does not match its box shape",
+            ));
+        }
+        Ok(Self {
+            n,
+            br,
+            bc,
+            solution: init.clone(),
+            init,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.n
+    }
+
+    /// Rows and columns of a box.
+    pub fn box_shape(&self) -> (usize, usize) {
+        (self.br, self.bc)
+    }
+
+    pub fn init(&self) -> &[u8] {
+        &self.init
+    }
+
+    pub fn solution(&self) -> &[u8] {
+        &self.solution
+    }
+
+    /// The minlex form of the givens, see `transform::minlex`, row-major. None for shapes
+    /// without a compiled `Sudoku` (see `is_specialized`) or givens out of range.
+    pub fn minlex(&self) -> Option<Vec<u8>> {
+        self.canonical(false, u64::MAX)
+    }
+
+    /// The form of the givens that `transform::minlex_givens_first` finds, row-major.
+    /// None like `minlex`, and also if the search needs more than `max_nodes` steps.
+    pub fn minlex_givens_first_within(&self, max_nodes: u64) -> Option<Vec<u8>> {
+        self.canonical(true, max_nodes)
+    }
+
+    fn canonical(&self, givens_first: bool, max_nodes: u64) -> Option<Vec<u8>> {
+        if self.init.iter().any(|&v| v as usize > self.n) {
+            return None;
+        }
+        minlex_dispatch(self, givens_first, max_nodes)
+    }
+
+    /// True if the solution holds every value once in each row, column and box.
+    pub fn check(&self) -> bool {
+        cnf::units(self.n, self.br, self.bc).all(|unit| {
+            let mut seen = vec![false; self.n + 1];
+            unit.iter().all(|&(r, c)| {
+                let v = self.solution[r * self.n + c] as usize;
+                (1..=self.n).contains(&v) && !std::mem::replace(&mut seen[v], true)
+            })
+        })
+    }
+}
+
+/// Defines `is_specialized` and the dispatch of `DynSolver` and `DynSudoku::minlex` from
+/// one list of shapes.
+macro_rules! specialized_shapes {
+    ($(($n:literal, $br:literal, $bc:literal)),* $(,)?) => {
+        /// True if `DynSolver` has a compiled `SolverEngine` for a grid of size `n`
+        /// with `br` x `bc` boxes, so the requested `Kind` is honoured.
+        pub fn is_specialized(n: usize, br: usize, bc: usize) -> bool {
+            matches!((n, br, bc), $(($n, $br, $bc))|*)
+        }
+
+        fn dispatch(solver: &mut DynSolver, s: &mut DynSudoku) -> Result<Backend, SolveError> {
+            match (s.n, s.br, s.bc) {
+                $(($n, $br, $bc) => solver.solve_sized::<$n, $br, $bc>(s),)*
+                _ => solver.solve_fallback(s),
+            }
+        }
+
+        fn minlex_dispatch(s: &DynSudoku, givens_first: bool, max_nodes: u64) -> Option<Vec<u8>> {
+            match (s.n, s.br, s.bc) {
+                $(($n, $br, $bc) => minlex_sized::<$n, $br, $bc>(s, givens_first, max_nodes),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+specialized_shapes!(
+    (4, 2, 2),
+    (6, 2, 3),
+    (6, 3, 2),
+    (8, 2, 4),
+    (8, 4, 2),
+    (9, 3, 3),
+    (12, 3, 4),
+    (12, 4, 3),
+    (16, 4, 4),
+);
+
+/// The solver that `DynSolver::solve` ran on a puzzle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The `SolverEngine` of the puzzle's shape, with the requested `Kind`.
+    Engine,
+    /// The CNF encoding and the built-in CDCL solver, whatever the `Kind`: the shape has
+    /// no compiled `SolverEngine` (see `is_specialized`).
+    Cdcl,
+}
+
+/// Solves runtime-sized puzzles with the `SolverEngine` of their shape, built on first
+/// use and kept for later puzzles of the same shape.
+///
+/// Shapes without one, including grids above 16x16, are solved through the CNF encoding
+/// and the built-in CDCL solver; `solve` returns which of the two ran.
+pub struct DynSolver {
+    kind: Kind,
+    cancel: CancelToken,
+    /// `SolverEngine<N, BR, BC>` of each `(N, BR, BC)` met so far.
+    engines: HashMap<(usize, usize, usize), Box<dyn Any + Send>>,
+}
+
+impl DynSolver {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            cancel: CancelToken::new(),
+            engines: HashMap::new(),
+        }
+    }
+
+    /// Makes `solve` return Err(SolveError::Cancelled) soon after `token` is cancelled,
+    /// as `SolverEngine::set_cancel` does.
+    pub fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = token;
+        // engines built so far hold the old token
+        self.engines.clear();
+    }
+
+    pub fn solve(&mut self, s: &mut DynSudoku) -> Result<Backend, SolveError> {
+        dispatch(self, s)
+    }
+
+    fn solve_sized<const N: usize, const BR: usize, const BC: usize>(
+        &mut self,
+        s: &mut DynSudoku,
+    ) -> Result<Backend, SolveError> {
+        let mut init = [[0u8; N]; N];
+        for (row, cells) in init.iter_mut().zip(s.init.chunks_exact(N)) {
+            row.copy_from_slice(cells);
+        }
+        let (kind, cancel) = (&self.kind, &self.cancel);
+        let engine = self
+            .engines
+            .entry((N, BR, BC))
+            .or_insert_with(|| {
+                let mut engine = SolverEngine::<N, BR, BC>::new(kind.clone());
+                engine.set_cancel(cancel.clone());
+                Box::new(engine)
+            })
+            .downcast_mut::<SolverEngine<N, BR, BC>>()
+            .expect("each shape has one engine type");
+        let mut sized = Sudoku::<N, BR, BC>::new(init);
+        engine.solve(&mut sized)?;
+        s.solution = sized.solution.as_flattened().to_vec();
+        Ok(Backend::Engine)
+    }
+
+    fn solve_fallback(&mut self, s: &mut DynSudoku) -> Result<Backend, SolveError> {
+        let formula = cnf::encode(s.n, s.br, s.bc, &s.init)?;
+        let mut cdcl = Cdcl::new(formula.num_vars);
+        for clause in &formula.clauses {
+            cdcl.add_clause(clause);
+        }
+        match cdcl.solve_until(|| self.cancel.is_cancelled()) {
+            None => return Err(SolveError::Cancelled),
+            Some(false) => return Err(SolveError::Unsolvable),
+            Some(true) => {}
+        }
+        s.solution = cnf::decode(s.n, |v| cdcl.model_value(v)).ok_or(SolveError::Unsolvable)?;
+        Ok(Backend::Cdcl)
+    }
+}
+
+/// Solves one runtime-sized puzzle with a `DynSolver` of its own, see there.
+pub fn solve_dyn(kind: &Kind, s: &mut DynSudoku) -> Result<Backend, SolveError> {
+    DynSolver::new(kind.clone()).solve(s)
+}
+
+fn minlex_sized<const N: usize, const BR: usize, const BC: usize>(
+    s: &DynSudoku,
+    givens_first: bool,
+    max_nodes: u64,
+) -> Option<Vec<u8>> {
+    let mut grid = [[0u8; N]; N];
+    for (row, cells) in grid.iter_mut().zip(s.init.chunks_exact(N)) {
+        row.copy_from_slice(cells);
+    }
+    let (canon, _) = if givens_first {
+        transform::minlex_givens_first_within::<N, BR, BC>(&grid, max_nodes)?
+    } else {
+        transform::minlex_within::<N, BR, BC>(&grid, max_nodes)?
+    };
+    Some(canon.as_flattened().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatches_known_shapes_and_falls_back() {
+        let mut init = vec![0u8; 36];
+        init[2] = 3;
+        init[10] = 2;
+        init[15] = 5;
+        let mut s = DynSudoku::new(2, 3, init).unwrap();
+        assert!(is_specialized(6, 2, 3));
+        assert_eq!(solve_dyn(&Kind::Dfs, &mut s), Ok(Backend::Engine));
+        assert!(s.check());
+        assert_eq!(s.solution()[2], 3);
+
+        // 10x10 with 2x5 boxes has no specialized engine
+        let mut init = vec![0u8; 100];
+        init[0] = 7;
+        init[55] = 10;
+        let mut s = DynSudoku::new(2, 5, init).unwrap();
+        assert!(!is_specialized(10, 2, 5));
+        assert_eq!(solve_dyn(&Kind::Dfs, &mut s), Ok(Backend::Cdcl));
+        assert!(s.check());
+        assert_eq!((s.solution()[0], s.solution()[55]), (7, 10));
+    }
+
+    #[test]
+    fn solver_keeps_engines_and_can_be_cancelled() {
+        let mut solver = DynSolver::new(Kind::Dfs);
+        for given in 1..=4 {
+            let mut init = vec![0u8; 16];
+            init[5] = given;
+            let mut s = DynSudoku::new(2, 2, init).unwrap();
+            assert_eq!(solver.solve(&mut s), Ok(Backend::Engine));
+            assert!(s.check());
+        }
+        assert_eq!(solver.engines.len(), 1);
+
+        let token = CancelToken::new();
+        solver.set_cancel(token.clone());
+        token.cancel();
+        let mut four = DynSudoku::new(2, 2, vec![0; 16]).unwrap();
+        let mut ten = DynSudoku::new(2, 5, vec![0; 100]).unwrap();
+        assert_eq!(solver.solve(&mut four), Err(SolveError::Cancelled));
+        assert_eq!(solver.solve(&mut ten), Err(SolveError::Cancelled));
+    }
+
+    #[test]
+    fn rejects_bad_shapes_and_givens() {
+        assert!(DynSudoku::new(3, 3, vec![0; 80]).is_err());
+        // (2^32)^2 wraps to 0 cells in release builds
+        assert!(DynSudoku::new(1 << 16, 1 << 16, Vec::new()).is_err());
+        assert!(DynSudoku::new(usize::MAX, 2, vec![0; 4]).is_err());
+
+        let mut init = vec![0u8; 100];
+        init[0] = 11;
+        let mut s = DynSudoku::new(2, 5, init).unwrap();
+        assert_eq!(
+            solve_dyn(&Kind::Dfs, &mut s),
+            Err(SolveError::InvalidPuzzle("given is out of range"))
+        );
+    }
+}
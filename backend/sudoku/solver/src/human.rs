@@ -0,0 +1,1164 @@
+use crate::{
+    dfs::DfsBacktracking,
+    solver::SolveError,
+    sudoku::{MAX_CELLS, Sudoku},
+};
+use std::fmt;
+
+/// A deduction of the human-style solver behind `Rater`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Technique {
+    /// The only place left for a value in a box, or in a row or column.
+    HiddenSingle {
+        in_box: bool,
+    },
+    /// Pointing whose eliminations leave a hidden single, which is placed with it.
+    DirectPointing,
+    /// Claiming whose eliminations leave a hidden single, which is placed with it.
+    DirectClaiming,
+    /// A hidden pair or triple whose eliminations leave a hidden single.
+    DirectHiddenSubset(usize),
+    /// The only candidate left in a cell.
+    NakedSingle,
+    /// A value of a box confined to one row or column, removed from the rest of that line.
+    Pointing,
+    /// A value of a row or column confined to one box, removed from the rest of the box.
+    Claiming,
+    /// `k` cells of a unit with `k` candidates between them: pair, triple or quad.
+    NakedSubset(usize),
+    /// `k` values of a unit confined to `k` cells.
+    HiddenSubset(usize),
+    /// X-Wing, Swordfish or Jellyfish for `k` = 2, 3, 4.
+    Fish(usize),
+    XyWing,
+    XyzWing,
+    /// An alternating inference chain (see `Chain`) on a single value, of `length` nodes.
+    XChain {
+        length: usize,
+    },
+    /// A chain through bivalue cells, each entered and left by its two values.
+    XyChain {
+        length: usize,
+    },
+    /// Any other alternating inference chain.
+    Aic {
+        length: usize,
+    },
+    /// Every candidate of a cell, or every place of a value in a unit, leads by singles
+    /// to the same placement or elimination. `length` counts the deductions of the
+    /// longest branch.
+    Forcing {
+        length: usize,
+    },
+    /// A candidate that, once placed, leads to a contradiction by singles alone
+    /// (`nesting` 0) or with contradictions of the level below. `length` counts the
+    /// deductions on the way.
+    Contradiction {
+        nesting: u8,
+        length: usize,
+    },
+}
+
+/// Sudoku Explainer's surcharge for long chains: a tenth each time `length` passes the
+/// next of 4, 6, 8, 12, 16, 24, ...
+fn length_bonus(length: usize) -> u32 {
+    let (mut ceil, mut bonus, mut odd) = (4, 0, false);
+    while length > ceil {
+        bonus += 1;
+        ceil = if odd { ceil * 4 / 3 } else { ceil * 3 / 2 };
+        odd = !odd;
+    }
+    bonus
+}
+
+fn subset_name(k: usize) -> &'static str {
+    match k {
+        2 => "pair",
+        3 => "triple",
+        _ => "quad",
+    }
+}
+
+impl Technique {
+    /// Difficulty on the Sudoku Explainer scale, from 1.2 for a hidden single in a box.
+    pub fn rating(&self) -> f32 {
+        self.tenths() as f32 / 10.0
+    }
+
+    fn tenths(&self) -> u32 {
+        match *self {
+            Technique::HiddenSingle { in_box: true } => 12,
+            Technique::HiddenSingle { in_box: false } => 15,
+            Technique::DirectPointing => 17,
+            Technique::DirectClaiming => 19,
+            Technique::DirectHiddenSubset(2) => 20,
+            Technique::DirectHiddenSubset(_) => 25,
+            Technique::NakedSingle => 23,
+            Technique::Pointing => 26,
+            Technique::Claiming => 28,
+            Technique::NakedSubset(2) => 30,
+            Technique::NakedSubset(3) => 36,
+            Technique::NakedSubset(_) => 50,
+            Technique::Fish(2) => 32,
+            Technique::Fish(3) => 38,
+            Technique::Fish(_) => 52,
+            Technique::HiddenSubset(2) => 34,
+            Technique::HiddenSubset(3) => 40,
+            Technique::HiddenSubset(_) => 54,
+            Technique::XyWing => 42,
+            Technique::XyzWing => 44,
+            Technique::XChain { length } => 66 + length_bonus(length),
+            Technique::XyChain { length } | Technique::Aic { length } => 70 + length_bonus(length),
+            Technique::Forcing { length } => 80 + length_bonus(length),
+            Technique::Contradiction { nesting, length } => {
+                85 + 10 * nesting as u32 + length_bonus(length)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Technique {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Technique::HiddenSingle { in_box: true } => write!(f, "Hidden single in a box"),
+            Technique::HiddenSingle { in_box: false } => write!(f, "Hidden single in a line"),
+            Technique::DirectPointing => write!(f, "Direct pointing"),
+            Technique::DirectClaiming => write!(f, "Direct claiming"),
+            Technique::DirectHiddenSubset(k) => write!(f, "Direct hidden {}", subset_name(k)),
+            Technique::NakedSingle => write!(f, "Naked single"),
+            Technique::Pointing => write!(f, "Pointing"),
+            Technique::Claiming => write!(f, "Claiming"),
+            Technique::NakedSubset(k) => write!(f, "Naked {}", subset_name(k)),
+            Technique::HiddenSubset(k) => write!(f, "Hidden {}", subset_name(k)),
+            Technique::Fish(2) => write!(f, "X-Wing"),
+            Technique::Fish(3) => write!(f, "Swordfish"),
+            Technique::Fish(_) => write!(f, "Jellyfish"),
+            Technique::XyWing => write!(f, "XY-Wing"),
+            Technique::XyzWing => write!(f, "XYZ-Wing"),
+            Technique::XChain { length } => write!(f, "X-Chain ({length} nodes)"),
+            Technique::XyChain { length } => write!(f, "XY-Chain ({length} nodes)"),
+            Technique::Aic { length } => write!(f, "Alternating inference chain ({length} nodes)"),
+            Technique::Forcing { length } => write!(f, "Forcing chains ({length} steps)"),
+            Technique::Contradiction { nesting: 0, length } => {
+                write!(f, "Forcing chain contradiction ({length} steps)")
+            }
+            Technique::Contradiction { nesting, length } => write!(
+                f,
+                "Nested forcing chain contradiction, level {nesting} ({length} steps)"
+            ),
+        }
+    }
+}
+
+/// An alternating inference chain of candidates (row, col, val): the first two nodes are
+/// linked strongly (at least one is true), the second and third weakly (not both are
+/// true) and so on, ending with a strong link. So at least one end is true, and any
+/// candidate that cannot be true together with either end goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    pub nodes: Vec<(usize, usize, u8)>,
+}
+
+impl fmt::Display for Chain {
+    /// Eureka notation, e.g. `(5)r1c2=(5)r1c7-(5)r3c8=(5)r3c1`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, &(row, col, val)) in self.nodes.iter().enumerate() {
+            let link = match i {
+                0 => "",
+                _ if i % 2 == 1 => "=",
+                _ => "-",
+            };
+            write!(f, "{link}({val})r{}c{}", row + 1, col + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// One deduction: the values it places and the candidates it removes, as (row, col, val).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub technique: Technique,
+    pub placements: Vec<(usize, usize, u8)>,
+    pub eliminations: Vec<(usize, usize, u8)>,
+    /// The chain behind a chain technique.
+    pub chain: Option<Chain>,
+}
+
+impl fmt::Display for Step {
+    /// E.g. `Pointing: r2c5<>4, r2c6<>4`, rows and columns counted from 1. Chains are
+    /// printed before their conclusions: `X-Chain (4 nodes): (5)r1c2=...=(5)r3c1 => r1c1<>5`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.technique)?;
+        if let Some(chain) = &self.chain {
+            write!(f, " {chain} =>")?;
+        }
+        let placed = self.placements.iter().map(|&(r, c, v)| (r, c, "=", v));
+        let removed = self.eliminations.iter().map(|&(r, c, v)| (r, c, "<>", v));
+        for (i, (row, col, op, val)) in placed.chain(removed).enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{sep}r{}c{}{op}{val}", row + 1, col + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rating of a puzzle and the path of deductions behind it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rating {
+    /// Rating of the hardest step, 0 for a puzzle without empty cells.
+    pub score: f32,
+    pub hardest: Option<Technique>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaterConfig {
+    /// Deepest `Technique::Contradiction` nesting tried before giving up with
+    /// `SolveError::GaveUp`.
+    pub max_nesting: u8,
+}
+
+impl Default for RaterConfig {
+    fn default() -> Self {
+        Self { max_nesting: 2 }
+    }
+}
+
+/// Rates puzzles on the Sudoku Explainer (SE) scale: solves them with the easiest
+/// technique that applies at every step and reports the hardest one needed.
+///
+/// Techniques up to Jellyfish and hidden quads rate as in SE, including its "direct"
+/// variants. Unique rectangles, BUG and aligned exclusions are not implemented, so
+/// puzzles needing them rate higher here. After those come alternating inference chains
+/// (X-chains from 6.6, XY-chains and other AICs from 7.0) and forcing chains by singles
+/// from 8.0, each plus SE's length surcharge. Beyond that everything is left to forcing
+/// chains that end in a contradiction: 8.5 plus the surcharge, a point more per level
+/// of nesting. SE's richer dynamic chains often rate such puzzles a few tenths apart,
+/// in either direction.
+pub struct Rater<const N: usize, const BR: usize, const BC: usize> {
+    config: RaterConfig,
+    dfs: DfsBacktracking<N, BR, BC>,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Default for Rater<N, BR, BC> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Rater<N, BR, BC> {
+    pub fn new() -> Self {
+        Self::with_config(RaterConfig::default())
+    }
+
+    pub fn with_config(config: RaterConfig) -> Self {
+        Self {
+            config,
+            dfs: DfsBacktracking::new(),
+        }
+    }
+
+    /// Returns Err(SolveError::Unsolvable) without a solution, Err(SolveError::InvalidPuzzle)
+    /// for several solutions or the inputs `solve` rejects, and Err(SolveError::GaveUp) if
+    /// no technique up to `max_nesting` applies.
+    pub fn rate(&mut self, s: &Sudoku<N, BR, BC>) -> Result<Rating, SolveError> {
+        let mut whole = Sudoku::with_candidates(s.init, s.candidates);
+        match self.dfs.count_solutions(&mut whole, 2)? {
+            0 => return Err(SolveError::Unsolvable),
+            1 => {}
+            _ => return Err(SolveError::InvalidPuzzle("puzzle has several solutions")),
+        }
+
+        let mut board = Board::<N, BR, BC>::new(s);
+        let mut rating = Rating {
+            score: 0.0,
+            hardest: None,
+            steps: Vec::new(),
+        };
+        let mut hardest = 0;
+        while !board.solved() {
+            let step = board
+                .next_step(self.config.max_nesting)
+                .ok_or(SolveError::GaveUp)?;
+            board.apply(&step);
+            if step.technique.tenths() > hardest {
+                hardest = step.technique.tenths();
+                rating.hardest = Some(step.technique);
+            }
+            rating.steps.push(step);
+        }
+        rating.score = hardest as f32 / 10.0;
+        Ok(rating)
+    }
+}
+
+/// Candidate removals (or placements) as (cell, val).
+type Elims = Vec<(usize, u8)>;
+
+/// Which links `Board::chain` may use.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ChainKind {
+    /// One value: strong links within units only.
+    X,
+    /// Bivalue cells: strong links within cells, weak ones between cells.
+    Xy,
+    Any,
+}
+
+/// Calls `f` with every `k`-element subset of `items`, in order.
+fn combinations<T: Copy>(items: &[T], k: usize, f: &mut impl FnMut(&[T])) {
+    fn go<T: Copy>(
+        items: &[T],
+        k: usize,
+        start: usize,
+        cur: &mut Vec<T>,
+        f: &mut impl FnMut(&[T]),
+    ) {
+        if cur.len() == k {
+            f(cur);
+            return;
+        }
+        for i in start..items.len() {
+            cur.push(items[i]);
+            go(items, k, i + 1, cur, f);
+            cur.pop();
+        }
+    }
+    go(items, k, 0, &mut Vec::with_capacity(k), f);
+}
+
+/// Values and pencilmarks of the human-style solver, cells row-major.
+///
+/// Not `dfs::Constraints`: that keeps bans apart from the unit masks and undoes changes
+/// through a trail, while techniques here remove single candidates for good and
+/// forcing chains try branches on plain copies of the board.
+#[derive(Clone, Copy)]
+struct Board<const N: usize, const BR: usize, const BC: usize> {
+    value: [u8; MAX_CELLS],
+    /// Candidates of empty cells, bit `val - 1` for `val`, 0 for filled ones.
+    cand: [u16; MAX_CELLS],
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Board<N, BR, BC> {
+    fn new(s: &Sudoku<N, BR, BC>) -> Self {
+        let mut board = Self {
+            value: [0; MAX_CELLS],
+            cand: [0; MAX_CELLS],
+        };
+        for cell in 0..N * N {
+            board.cand[cell] = s.candidates[cell / N][cell % N];
+        }
+        for cell in 0..N * N {
+            let val = s.init[cell / N][cell % N];
+            if val != 0 {
+                board.place(cell, val);
+            }
+        }
+        board
+    }
+
+    fn place(&mut self, cell: usize, val: u8) {
+        self.value[cell] = val;
+        self.cand[cell] = 0;
+        for &p in Sudoku::<N, BR, BC>::peers(cell) {
+            self.cand[p as usize] &= !(1 << (val - 1));
+        }
+    }
+
+    fn apply(&mut self, step: &Step) {
+        for &(row, col, val) in &step.eliminations {
+            self.cand[row * N + col] &= !(1 << (val - 1));
+        }
+        for &(row, col, val) in &step.placements {
+            self.place(row * N + col, val);
+        }
+    }
+
+    fn solved(&self) -> bool {
+        self.value[..N * N].iter().all(|&v| v != 0)
+    }
+
+    /// Cells of `unit` that may take the value of `bit`.
+    fn places(&self, unit: usize, bit: u16) -> impl Iterator<Item = usize> + '_ {
+        Sudoku::<N, BR, BC>::unit(unit)
+            .iter()
+            .map(|&c| c as usize)
+            .filter(move |&c| self.cand[c] & bit != 0)
+    }
+
+    fn step(technique: Technique, placements: &[(usize, u8)], elims: &[(usize, u8)]) -> Step {
+        let rcv = |&(cell, val): &(usize, u8)| (cell / N, cell % N, val);
+        Step {
+            technique,
+            placements: placements.iter().map(rcv).collect(),
+            eliminations: elims.iter().map(rcv).collect(),
+            chain: None,
+        }
+    }
+
+    /// The easiest deduction available, trying techniques by rating.
+    fn next_step(&self, max_nesting: u8) -> Option<Step> {
+        let single = |in_box| {
+            self.hidden_single(in_box)
+                .map(|p| Self::step(Technique::HiddenSingle { in_box }, &[p], &[]))
+        };
+        let direct = |technique, found: Vec<Elims>| {
+            found.into_iter().find_map(|elims| {
+                let p = self.direct_single(&elims)?;
+                Some(Self::step(technique, &[p], &elims))
+            })
+        };
+        let first = |technique, found: Vec<Elims>| {
+            found
+                .into_iter()
+                .next()
+                .map(|elims| Self::step(technique, &[], &elims))
+        };
+
+        single(true)
+            .or_else(|| single(false))
+            .or_else(|| direct(Technique::DirectPointing, self.locked(true)))
+            .or_else(|| direct(Technique::DirectClaiming, self.locked(false)))
+            .or_else(|| direct(Technique::DirectHiddenSubset(2), self.hidden_subsets(2)))
+            .or_else(|| {
+                self.naked_single()
+                    .map(|p| Self::step(Technique::NakedSingle, &[p], &[]))
+            })
+            .or_else(|| direct(Technique::DirectHiddenSubset(3), self.hidden_subsets(3)))
+            .or_else(|| first(Technique::Pointing, self.locked(true)))
+            .or_else(|| first(Technique::Claiming, self.locked(false)))
+            .or_else(|| first(Technique::NakedSubset(2), self.naked_subsets(2)))
+            .or_else(|| first(Technique::Fish(2), self.fish(2)))
+            .or_else(|| first(Technique::HiddenSubset(2), self.hidden_subsets(2)))
+            .or_else(|| first(Technique::NakedSubset(3), self.naked_subsets(3)))
+            .or_else(|| first(Technique::Fish(3), self.fish(3)))
+            .or_else(|| first(Technique::HiddenSubset(3), self.hidden_subsets(3)))
+            .or_else(|| first(Technique::XyWing, self.wings(false)))
+            .or_else(|| first(Technique::XyzWing, self.wings(true)))
+            .or_else(|| first(Technique::NakedSubset(4), self.naked_subsets(4)))
+            .or_else(|| first(Technique::Fish(4), self.fish(4)))
+            .or_else(|| first(Technique::HiddenSubset(4), self.hidden_subsets(4)))
+            .or_else(|| {
+                // the cheapest chain of any kind, SE lets length outweigh the kind
+                let rcv = |&(cell, val): &(usize, u8)| (cell / N, cell % N, val);
+                [ChainKind::X, ChainKind::Xy, ChainKind::Any]
+                    .into_iter()
+                    .filter_map(|kind| {
+                        let (nodes, elims) = self.chain(kind)?;
+                        let length = nodes.len();
+                        let technique = match kind {
+                            ChainKind::X => Technique::XChain { length },
+                            ChainKind::Xy => Technique::XyChain { length },
+                            ChainKind::Any => Technique::Aic { length },
+                        };
+                        Some(Step {
+                            chain: Some(Chain {
+                                nodes: nodes.iter().map(rcv).collect(),
+                            }),
+                            ..Self::step(technique, &[], &elims)
+                        })
+                    })
+                    .min_by_key(|step| step.technique.tenths())
+            })
+            .or_else(|| {
+                let (placements, elims, length) = self.forcing()?;
+                Some(Self::step(
+                    Technique::Forcing { length },
+                    &placements,
+                    &elims,
+                ))
+            })
+            .or_else(|| {
+                (0..=max_nesting).find_map(|nesting| {
+                    let (cell, val, length) = self.contradiction(nesting)?;
+                    Some(Self::step(
+                        Technique::Contradiction { nesting, length },
+                        &[],
+                        &[(cell, val)],
+                    ))
+                })
+            })
+    }
+
+    fn hidden_single(&self, in_box: bool) -> Option<(usize, u8)> {
+        let units = if in_box { 2 * N..3 * N } else { 0..2 * N };
+        for unit in units {
+            for val in 1..=N as u8 {
+                let mut places = self.places(unit, 1 << (val - 1));
+                if let (Some(cell), None) = (places.next(), places.next()) {
+                    return Some((cell, val));
+                }
+            }
+        }
+        None
+    }
+
+    fn naked_single(&self) -> Option<(usize, u8)> {
+        (0..N * N)
+            .find(|&c| self.value[c] == 0 && self.cand[c].count_ones() == 1)
+            .map(|c| (c, self.cand[c].trailing_zeros() as u8 + 1))
+    }
+
+    /// The hidden single that `elims` leave in a unit of one of their cells, if any.
+    fn direct_single(&self, elims: &[(usize, u8)]) -> Option<(usize, u8)> {
+        let mut after = *self;
+        for &(cell, val) in elims {
+            after.cand[cell] &= !(1 << (val - 1));
+        }
+        elims.iter().find_map(|&(cell, val)| {
+            Sudoku::<N, BR, BC>::units_of(cell)
+                .iter()
+                .find_map(|&unit| {
+                    let mut places = after.places(unit, 1 << (val - 1));
+                    match (places.next(), places.next()) {
+                        (Some(p), None) => Some((p, val)),
+                        _ => None,
+                    }
+                })
+        })
+    }
+
+    /// Pointing (`pointing`) or claiming eliminations, one set per box and line.
+    fn locked(&self, pointing: bool) -> Vec<Elims> {
+        let mut found = Vec::new();
+        let units = if pointing { 2 * N..3 * N } else { 0..2 * N };
+        for unit in units {
+            for val in 1..=N as u8 {
+                let bit = 1 << (val - 1);
+                let cells: Vec<usize> = self.places(unit, bit).collect();
+                if cells.len() < 2 {
+                    continue;
+                }
+                // the other unit all the cells share: a line for pointing, the box for claiming
+                let shared = |k: usize| {
+                    let u = Sudoku::<N, BR, BC>::units_of(cells[0])[k];
+                    cells
+                        .iter()
+                        .all(|&c| Sudoku::<N, BR, BC>::units_of(c)[k] == u)
+                        .then_some(u)
+                };
+                let targets = if pointing {
+                    [shared(0), shared(1)]
+                } else {
+                    [shared(2), None]
+                };
+                for target in targets.into_iter().flatten() {
+                    let elims: Elims = self
+                        .places(target, bit)
+                        .filter(|c| !cells.contains(c))
+                        .map(|c| (c, val))
+                        .collect();
+                    if !elims.is_empty() {
+                        found.push(elims);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    fn naked_subsets(&self, k: usize) -> Vec<Elims> {
+        let mut found = Vec::new();
+        for unit in 0..3 * N {
+            let cells = Sudoku::<N, BR, BC>::unit(unit);
+            let open: Vec<usize> = cells
+                .iter()
+                .map(|&c| c as usize)
+                .filter(|&c| (2..=k as u32).contains(&self.cand[c].count_ones()))
+                .collect();
+            combinations(&open, k, &mut |subset| {
+                let union = subset.iter().fold(0, |m, &c| m | self.cand[c]);
+                if union.count_ones() as usize != k {
+                    return;
+                }
+                let elims: Elims = cells
+                    .iter()
+                    .map(|&c| c as usize)
+                    .filter(|c| !subset.contains(c))
+                    .flat_map(|c| {
+                        (1..=N as u8)
+                            .filter(move |&v| self.cand[c] & union & (1 << (v - 1)) != 0)
+                            .map(move |v| (c, v))
+                    })
+                    .collect();
+                if !elims.is_empty() {
+                    found.push(elims);
+                }
+            });
+        }
+        found
+    }
+
+    fn hidden_subsets(&self, k: usize) -> Vec<Elims> {
+        let mut found = Vec::new();
+        for unit in 0..3 * N {
+            let cells = Sudoku::<N, BR, BC>::unit(unit);
+            // positions within the unit of every value with 2..=k places
+            let mut spots = Vec::new();
+            for val in 1..=N as u8 {
+                let mask = cells
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &c)| self.cand[c as usize] & (1 << (val - 1)) != 0)
+                    .fold(0u32, |m, (i, _)| m | 1 << i);
+                if (2..=k as u32).contains(&mask.count_ones()) {
+                    spots.push((val, mask));
+                }
+            }
+            combinations(&spots, k, &mut |subset| {
+                let union = subset.iter().fold(0, |m, &(_, mask)| m | mask);
+                if union.count_ones() as usize != k {
+                    return;
+                }
+                let keep = subset.iter().fold(0u16, |m, &(v, _)| m | 1 << (v - 1));
+                let elims: Elims = (0..N)
+                    .filter(|&i| union & (1 << i) != 0)
+                    .map(|i| cells[i] as usize)
+                    .flat_map(|c| {
+                        (1..=N as u8)
+                            .filter(move |&v| self.cand[c] & !keep & (1 << (v - 1)) != 0)
+                            .map(move |v| (c, v))
+                    })
+                    .collect();
+                if !elims.is_empty() {
+                    found.push(elims);
+                }
+            });
+        }
+        found
+    }
+
+    fn fish(&self, k: usize) -> Vec<Elims> {
+        let mut found = Vec::new();
+        for val in 1..=N as u8 {
+            let bit = 1 << (val - 1);
+            for by_rows in [true, false] {
+                let cell = |line: usize, pos: usize| {
+                    if by_rows {
+                        line * N + pos
+                    } else {
+                        pos * N + line
+                    }
+                };
+                let lines: Vec<(usize, u32)> = (0..N)
+                    .map(|line| {
+                        let mask = (0..N)
+                            .filter(|&pos| self.cand[cell(line, pos)] & bit != 0)
+                            .fold(0u32, |m, pos| m | 1 << pos);
+                        (line, mask)
+                    })
+                    .filter(|&(_, mask)| (2..=k as u32).contains(&mask.count_ones()))
+                    .collect();
+                combinations(&lines, k, &mut |base| {
+                    let cover = base.iter().fold(0, |m, &(_, mask)| m | mask);
+                    if cover.count_ones() as usize != k {
+                        return;
+                    }
+                    let elims: Elims = (0..N)
+                        .filter(|&line| base.iter().all(|&(l, _)| l != line))
+                        .flat_map(|line| {
+                            (0..N)
+                                .filter(move |&pos| cover & (1 << pos) != 0)
+                                .map(move |pos| cell(line, pos))
+                        })
+                        .filter(|&c| self.cand[c] & bit != 0)
+                        .map(|c| (c, val))
+                        .collect();
+                    if !elims.is_empty() {
+                        found.push(elims);
+                    }
+                });
+            }
+        }
+        found
+    }
+
+    /// XY-Wings, or XYZ-Wings with `xyz`: a pivot and two bivalue pincers among its
+    /// peers that share a value `z`, which goes from every cell seeing all the wing's
+    /// cells holding `z`.
+    fn wings(&self, xyz: bool) -> Vec<Elims> {
+        let mut found = Vec::new();
+        let sees = |a: usize, b: usize| Sudoku::<N, BR, BC>::peers(a).contains(&(b as u8));
+        for pivot in 0..N * N {
+            let pc = self.cand[pivot];
+            if pc.count_ones() != if xyz { 3 } else { 2 } {
+                continue;
+            }
+            let pincers: Vec<usize> = Sudoku::<N, BR, BC>::peers(pivot)
+                .iter()
+                .map(|&p| p as usize)
+                .filter(|&p| {
+                    let c = self.cand[p];
+                    c.count_ones() == 2
+                        && if xyz {
+                            c & pc == c
+                        } else {
+                            (c & pc).count_ones() == 1
+                        }
+                })
+                .collect();
+            combinations(&pincers, 2, &mut |pair| {
+                let (a, b) = (self.cand[pair[0]], self.cand[pair[1]]);
+                let z = a & b;
+                let shape = if xyz { a | b == pc } else { a ^ b == pc };
+                if a == b || z.count_ones() != 1 || !shape {
+                    return;
+                }
+                let mut wing = vec![pair[0], pair[1]];
+                if xyz {
+                    wing.push(pivot);
+                }
+                let elims: Elims = (0..N * N)
+                    .filter(|&c| self.cand[c] & z != 0 && !wing.contains(&c))
+                    .filter(|&c| wing.iter().all(|&w| sees(c, w)))
+                    .map(|c| (c, z.trailing_zeros() as u8 + 1))
+                    .collect();
+                if !elims.is_empty() {
+                    found.push(elims);
+                }
+            });
+        }
+        found
+    }
+
+    /// The shortest alternating inference chain of `kind` that eliminates something, as
+    /// its nodes (cell, val) and the eliminations.
+    ///
+    /// Nodes are candidates, `cell * N + val - 1`. A breadth-first search from every
+    /// candidate assumed false alternates strong links, which make the next node true,
+    /// and weak ones, which make it false; every node reached true closes a chain.
+    fn chain(&self, kind: ChainKind) -> Option<(Vec<(usize, u8)>, Elims)> {
+        let nodes = N * N * N;
+        let has = |node: usize| self.cand[node / N] & (1 << (node % N)) != 0;
+        // strong links: the two places of a value in a unit, the two values of a cell
+        let mut strong = vec![Vec::new(); nodes];
+        if kind != ChainKind::Xy {
+            for unit in 0..3 * N {
+                for v in 0..N {
+                    let mut places = self.places(unit, 1 << v);
+                    if let (Some(a), Some(b), None) = (places.next(), places.next(), places.next())
+                    {
+                        strong[a * N + v].push(b * N + v);
+                        strong[b * N + v].push(a * N + v);
+                    }
+                }
+            }
+        }
+        if kind != ChainKind::X {
+            for cell in (0..N * N).filter(|&c| self.cand[c].count_ones() == 2) {
+                let a = cell * N + self.cand[cell].trailing_zeros() as usize;
+                let b = cell * N + 15 - self.cand[cell].leading_zeros() as usize;
+                strong[a].push(b);
+                strong[b].push(a);
+            }
+        }
+        // weak links: the same value in peer cells, other values of the same cell
+        let weak = |node: usize| {
+            let (cell, v) = (node / N, node % N);
+            let same_value = Sudoku::<N, BR, BC>::peers(cell)
+                .iter()
+                .map(move |&p| p as usize * N + v);
+            let same_cell = (kind == ChainKind::Any)
+                .then(|| (0..N).filter(move |&w| w != v).map(move |w| cell * N + w))
+                .into_iter()
+                .flatten();
+            same_value.chain(same_cell).filter(move |&n| has(n))
+        };
+        let sees = |a: usize, b: usize| {
+            a != b
+                && if a / N == b / N {
+                    true
+                } else {
+                    a % N == b % N && Sudoku::<N, BR, BC>::peers(a / N).contains(&((b / N) as u8))
+                }
+        };
+
+        let mut best: Option<(Vec<(usize, u8)>, Elims)> = None;
+        // a chain holds each candidate once, so every search from a start reaches a node
+        // at most once; links alternate, so the start is false and a node at an even
+        // depth is true. `seen` holds the number of the search that reached a node last.
+        let mut seen = vec![usize::MAX; nodes];
+        let mut parent = vec![0; nodes];
+        let mut depth = vec![0; nodes];
+        let mut queue = std::collections::VecDeque::new();
+        for start in (0..nodes).filter(|&n| has(n) && !strong[n].is_empty()) {
+            queue.clear();
+            seen[start] = start;
+            depth[start] = 1;
+            queue.push_back(start);
+            while let Some(node) = queue.pop_front() {
+                let d = depth[node];
+                if best.as_ref().is_some_and(|b| d >= b.0.len()) {
+                    break;
+                }
+                let on = d.is_multiple_of(2);
+                if on {
+                    let elims: Elims = (0..nodes)
+                        .filter(|&z| has(z) && sees(z, start) && sees(z, node))
+                        .map(|z| (z / N, (z % N) as u8 + 1))
+                        .collect();
+                    if !elims.is_empty() {
+                        let mut path = vec![node];
+                        while path.len() < d {
+                            path.push(parent[path[path.len() - 1]]);
+                        }
+                        let chain = path
+                            .iter()
+                            .rev()
+                            .map(|&n| (n / N, (n % N) as u8 + 1))
+                            .collect();
+                        best = Some((chain, elims));
+                        break;
+                    }
+                }
+                let next: Vec<usize> = if on {
+                    weak(node).collect()
+                } else {
+                    strong[node].clone()
+                };
+                for n in next {
+                    if seen[n] != start {
+                        seen[n] = start;
+                        parent[n] = node;
+                        depth[n] = d + 1;
+                        queue.push_back(n);
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Forcing chains: the placements and eliminations that every branch of a cell's
+    /// candidates, or of a value's places in a unit, leads to by singles. Branches that
+    /// end in a contradiction are left out. Returns the first such set found and the
+    /// deductions of its longest branch.
+    fn forcing(&self) -> Option<(Elims, Elims, usize)> {
+        let cells = (0..N * N)
+            .filter(|&c| self.cand[c].count_ones() >= 2)
+            .map(|c| {
+                (1..=N as u8)
+                    .filter(|&v| self.cand[c] & (1 << (v - 1)) != 0)
+                    .map(|v| (c, v))
+                    .collect::<Vec<_>>()
+            });
+        let units = (0..3 * N).flat_map(|unit| {
+            (1..=N as u8).filter_map(move |v| {
+                let places: Vec<(usize, u8)> =
+                    self.places(unit, 1 << (v - 1)).map(|c| (c, v)).collect();
+                (places.len() >= 2).then_some(places)
+            })
+        });
+        cells.chain(units).find_map(|branches| {
+            let mut outcomes = Vec::new();
+            let mut length = 0;
+            for &(cell, val) in &branches {
+                let mut assumed = *self;
+                assumed.place(cell, val);
+                let mut len = 1;
+                if assumed.propagate_singles(&mut len) {
+                    outcomes.push(assumed);
+                }
+                length = length.max(len);
+            }
+            let placements: Elims = (0..N * N)
+                .filter(|&c| self.value[c] == 0)
+                .filter_map(|c| {
+                    let v = outcomes.first()?.value[c];
+                    (v != 0 && outcomes.iter().all(|o| o.value[c] == v)).then_some((c, v))
+                })
+                .collect();
+            let elims: Elims = (0..N * N)
+                .flat_map(|c| (1..=N as u8).map(move |v| (c, v)))
+                .filter(|&(c, v)| {
+                    let bit = 1 << (v - 1);
+                    self.cand[c] & bit != 0
+                        && !outcomes.is_empty()
+                        && outcomes
+                            .iter()
+                            .all(|o| o.cand[c] & bit == 0 && o.value[c] != v)
+                })
+                .filter(|&(c, _)| placements.iter().all(|&(p, _)| p != c))
+                .collect();
+            (!placements.is_empty() || !elims.is_empty()).then_some((placements, elims, length))
+        })
+    }
+
+    /// The candidate whose placement leads to a contradiction in the fewest deductions,
+    /// found by propagating singles and, for `nesting` above 0, contradictions of the
+    /// level below. Returns (cell, val, length).
+    fn contradiction(&self, nesting: u8) -> Option<(usize, u8, usize)> {
+        let mut best: Option<(usize, u8, usize)> = None;
+        for cell in 0..N * N {
+            for val in (1..=N as u8).filter(|&v| self.cand[cell] & (1 << (v - 1)) != 0) {
+                let mut assumed = *self;
+                assumed.place(cell, val);
+                let mut length = 1;
+                let limit = best.map_or(usize::MAX, |b| b.2 - 1);
+                if !assumed.propagate(nesting, &mut length, limit) && length <= limit {
+                    best = Some((cell, val, length));
+                }
+            }
+        }
+        best
+    }
+
+    /// Like `contradiction`, but the first one found looking from candidate `start`
+    /// (`cell * N + val - 1`) on, wrapping around. Returns (cell, val, length, next start).
+    fn first_contradiction(&self, nesting: u8, start: usize) -> Option<(usize, u8, usize, usize)> {
+        (start..N * N * N).chain(0..start).find_map(|i| {
+            let (cell, val) = (i / N, (i % N) as u8 + 1);
+            if self.cand[cell] & (1 << (val - 1)) == 0 {
+                return None;
+            }
+            let mut assumed = *self;
+            assumed.place(cell, val);
+            let mut length = 1;
+            (!assumed.propagate(nesting, &mut length, usize::MAX)).then_some((cell, val, length, i))
+        })
+    }
+
+    /// Applies singles, then contradictions below `nesting`, until neither applies or
+    /// `length`, the count of deductions made, passes `limit`. Returns false on a
+    /// contradiction.
+    fn propagate(&mut self, nesting: u8, length: &mut usize, limit: usize) -> bool {
+        let mut start = 0;
+        loop {
+            if !self.propagate_singles(length) {
+                return false;
+            }
+            if nesting == 0 || *length > limit || self.solved() {
+                return true;
+            }
+            let Some((cell, val, len, next)) = self.first_contradiction(nesting - 1, start) else {
+                return true;
+            };
+            self.cand[cell] &= !(1 << (val - 1));
+            *length += len;
+            start = next;
+        }
+    }
+
+    /// Places naked and hidden singles until there are none. Returns false if a cell
+    /// runs out of candidates or a value out of places in some unit.
+    fn propagate_singles(&mut self, length: &mut usize) -> bool {
+        loop {
+            let mut progress = false;
+            for cell in 0..N * N {
+                if self.value[cell] != 0 {
+                    continue;
+                }
+                match self.cand[cell].count_ones() {
+                    0 => return false,
+                    1 => {
+                        self.place(cell, self.cand[cell].trailing_zeros() as u8 + 1);
+                        *length += 1;
+                        progress = true;
+                    }
+                    _ => {}
+                }
+            }
+            for unit in 0..3 * N {
+                let (mut placed, mut once, mut twice) = (0u16, 0u16, 0u16);
+                for &c in Sudoku::<N, BR, BC>::unit(unit) {
+                    let c = c as usize;
+                    if self.value[c] != 0 {
+                        placed |= 1 << (self.value[c] - 1);
+                    } else {
+                        twice |= once & self.cand[c];
+                        once |= self.cand[c];
+                    }
+                }
+                let full = u16::MAX >> (16 - N);
+                if (placed | once) != full {
+                    return false;
+                }
+                let single = once & !twice & !placed;
+                if single != 0 {
+                    let bit = single & single.wrapping_neg();
+                    let cell = self.places(unit, bit).next().expect("a single has a place");
+                    self.place(cell, bit.trailing_zeros() as u8 + 1);
+                    *length += 1;
+                    progress = true;
+                }
+            }
+            if !progress {
+                return true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::{MinimizeConfig, RemovalOrder, Symmetry, minimize},
+        sudoku::Sudoku9,
+        test_util::{HARD, parse9},
+        transform::Transform,
+    };
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    #[test]
+    fn curated_ratings() {
+        // (puzzle, rating, hardest technique), one or two per level of the ladder. These
+        // pin this rater's own output so changes to it show up; they were not checked
+        // against Sudoku Explainer, so they say nothing about agreeing with it.
+        let rated = [
+            (
+                "53..7....6..195....98....6.8...6...34..8.3..17...2...6.6....28....419..5....8..79",
+                1.2,
+                Technique::HiddenSingle { in_box: true },
+            ),
+            (
+                "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......",
+                2.6,
+                Technique::Pointing,
+            ),
+            (
+                "2.39....67.1...9.....8....4.5..1.......4...3...2..8.........5..43.15....6...2..43",
+                3.0,
+                Technique::NakedSubset(2),
+            ),
+            (
+                ".7......39...7..6....2....4.....4.51.......9..3.92.......4.26..5.7..38...845.....",
+                3.2,
+                Technique::Fish(2),
+            ),
+            (
+                "..1.89.2....6..........263..2....56.789......4............567...182.....6....3..9",
+                3.4,
+                Technique::HiddenSubset(2),
+            ),
+            (
+                "..658..4........6......2..3..51..3........814...2.9...95.4.7...61.....8..7.....3.",
+                3.6,
+                Technique::NakedSubset(3),
+            ),
+            (
+                "8....4..1.4.6.5....9.3..4.74.6.....57.........3...8.....2.5..7.....3..8...7..69..",
+                3.8,
+                Technique::Fish(3),
+            ),
+            (
+                "3.5.6.8....4..7...6..4...35.....1....9....4........167...8......5..1.346.4.7.3.8.",
+                4.2,
+                Technique::XyWing,
+            ),
+            (
+                "3...281......7.3....8....46.942...8.1.6.........3....546.....3.....47....2.5.....",
+                4.4,
+                Technique::XyzWing,
+            ),
+            (
+                ".1....2....3.9.4..7.9.8......8..4.9.32.........1.....5.4.6.3.5......86...8....92.",
+                6.6,
+                Technique::XChain { length: 4 },
+            ),
+            (
+                "..4.7..25.79..3...5.2.8....7....62...8......1...51.4.........78.....2.1..1..38...",
+                6.7,
+                Technique::XChain { length: 6 },
+            ),
+            (
+                "....3........1...4...2.61.9..5...4363.2............7.......7.4.1.85.3....7..6...2",
+                7.1,
+                Technique::Aic { length: 6 },
+            ),
+            (
+                "...3..1.......9....4.57.8.3..9.18.7..5....3...76.....9.....358..9..5......7..6..2",
+                7.4,
+                Technique::Aic { length: 14 },
+            ),
+            (
+                ".8.....65....6.7....3.......792......6.3.4...4.......824...98......872.9....3.1..",
+                8.4,
+                Technique::Forcing { length: 13 },
+            ),
+            (
+                ".7...2.9...5......2...1...478..2...9.5.3...7..3...8..2....73..836...1......4.97..",
+                8.7,
+                Technique::Forcing { length: 40 },
+            ),
+            (
+                HARD,
+                10.6,
+                Technique::Contradiction {
+                    nesting: 1,
+                    length: 148,
+                },
+            ),
+        ];
+        let tenths = |r: f32| (r * 10.0).round() as i32;
+        let mut rater = Rater::<9, 3, 3>::new();
+        for (p, expected, hardest) in rated {
+            let rating = rater.rate(&Sudoku9::new(parse9(p))).unwrap();
+            assert_eq!(tenths(rating.score), tenths(expected), "{p}");
+            assert_eq!(rating.hardest, Some(hardest), "{p}");
+        }
+
+        let wiki = rater.rate(&Sudoku9::new(parse9(rated[0].0))).unwrap();
+        assert_eq!(wiki.hardest, Some(Technique::HiddenSingle { in_box: true }));
+        assert_eq!(wiki.steps[0].to_string(), "Hidden single in a box: r1c6=8");
+    }
+
+    #[test]
+    fn steps_agree_with_the_solution() {
+        let mut dfs = DfsBacktracking::<9, 3, 3>::new();
+        let mut rater = Rater::<9, 3, 3>::new();
+        let mut base = Sudoku9::new(parse9(
+            "4.....8.5.3..........7......2.....6.....8.4......1.......6.3.7.5..2.....1.4......",
+        ));
+        dfs.solve(&mut base).unwrap();
+        let mut rng = SmallRng::seed_from_u64(9);
+        let mut seen = std::collections::HashSet::new();
+        for seed in 0..6 {
+            let grid = Transform::<9, 3, 3>::random(&mut rng).apply(&base.solution);
+            let config = MinimizeConfig {
+                order: RemovalOrder::Seeded(seed),
+                symmetry: Symmetry::None,
+            };
+            let puzzle = minimize(&Sudoku9::new(grid), &config).unwrap();
+            let rating = rater.rate(&Sudoku9::new(puzzle)).unwrap();
+            for step in &rating.steps {
+                seen.insert(std::mem::discriminant(&step.technique));
+                for &(r, c, v) in &step.placements {
+                    assert_eq!(grid[r][c], v, "{step}");
+                }
+                for &(r, c, v) in &step.eliminations {
+                    assert_ne!(grid[r][c], v, "{step}");
+                }
+            }
+        }
+        assert!(seen.len() > 4);
+    }
+
+    #[test]
+    fn chain_steps_are_sound() {
+        let p = HARD;
+        let mut solved = Sudoku9::new(parse9(p));
+        DfsBacktracking::new().solve(&mut solved).unwrap();
+        let rating = Rater::<9, 3, 3>::new()
+            .rate(&Sudoku9::new(parse9(p)))
+            .unwrap();
+        let chains: Vec<&Step> = rating.steps.iter().filter(|s| s.chain.is_some()).collect();
+        assert!(!chains.is_empty());
+        for step in chains {
+            let nodes = &step.chain.as_ref().unwrap().nodes;
+            assert!(nodes.len() >= 4 && nodes.len() % 2 == 0, "{step}");
+            let distinct: std::collections::HashSet<_> = nodes.iter().collect();
+            assert_eq!(distinct.len(), nodes.len(), "{step}");
+            // at least one end of the chain holds
+            let holds = |&(r, c, v): &(usize, usize, u8)| solved.solution[r][c] == v;
+            assert!(holds(&nodes[0]) || holds(nodes.last().unwrap()), "{step}");
+            for &(r, c, v) in &step.eliminations {
+                assert_ne!(solved.solution[r][c], v, "{step}");
+            }
+        }
+        assert!(
+            rating.steps.iter().any(|s| s.to_string()
+                == "X-Chain (4 nodes): (4)r1c3=(4)r4c3-(4)r4c8=(4)r6c9 => r1c9<>4")
+        );
+    }
+}
@@ -1,3 +1,22 @@
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+pub mod analysis;
+pub mod annealing;
+pub mod batch;
+pub mod cnf;
 pub mod dfs;
+/// The copy-per-node DFS that `dfs` replaced, kept for benchmarks only.
+#[doc(hidden)]
+pub mod dfs_fork;
+pub mod dynamic;
+pub mod human;
+pub mod noisy;
+pub mod portfolio;
+pub mod sampling;
+pub mod sat;
 pub mod solver;
+pub mod store;
 pub mod sudoku;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod transform;
@@ -0,0 +1,382 @@
+use crate::{
+    dfs::{DfsBacktracking, DfsConfig, Propagation},
+    solver::SolveError,
+    sudoku::Sudoku,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoisyConfig {
+    /// Only accept readings whose puzzle has exactly one solution. Without it the most
+    /// probable solvable reading wins and `Reading::unique` tells whether it is proper.
+    pub require_unique: bool,
+    /// Readings checked by the solver before giving up with `SolveError::GaveUp`.
+    pub max_checks: u64,
+}
+
+impl Default for NoisyConfig {
+    fn default() -> Self {
+        Self {
+            require_unique: true,
+            max_checks: 10_000,
+        }
+    }
+}
+
+/// A cell whose chosen reading is not its most probable one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Override {
+    pub row: usize,
+    pub col: usize,
+    /// The most probable value, `0` for empty.
+    pub read: u8,
+    pub chosen: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading<const N: usize> {
+    pub givens: [[u8; N]; N],
+    pub solution: [[u8; N]; N],
+    pub overridden: Vec<Override>,
+    /// Natural log of the probability of the reading, the sum over the cells.
+    pub log_prob: f64,
+    /// True if the givens have exactly one solution.
+    pub unique: bool,
+}
+
+/// Counters of the last `solve` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NoisyStats {
+    /// Readings handed to the solver.
+    pub checks: u64,
+    /// Cost thresholds tried, see `NoisyGivens`.
+    pub rounds: u32,
+}
+
+/// Finds the most probable givens that make a valid puzzle from per-cell probabilities,
+/// e.g. the scores of an OCR model.
+///
+/// The cost of a reading is the sum of `-ln p` over its cells. The search is a depth-first
+/// branch and bound over the cells, least certain first, that never repeats a value in a
+/// unit and skips any branch above a cost threshold. Complete readings are checked with
+/// `DfsBacktracking::count_solutions`. The threshold starts at the cost of the most
+/// probable reading and grows until a round finds an accepted reading. The cheapest one
+/// of that round is the most probable of all.
+pub struct NoisyGivens<const N: usize, const BR: usize, const BC: usize> {
+    config: NoisyConfig,
+    dfs: DfsBacktracking<N, BR, BC>,
+    stats: NoisyStats,
+}
+
+/// Options of one cell in the search: value (`0` for empty) and cost, cheapest first.
+type Options = Vec<(u8, f64)>;
+
+/// The cheapest accepted reading of a round.
+struct Best<const N: usize> {
+    cost: f64,
+    givens: [[u8; N]; N],
+    solution: [[u8; N]; N],
+    unique: bool,
+}
+
+struct Round<'a, const N: usize> {
+    order: &'a [usize],
+    options: &'a [Options],
+    /// Cheapest cost of the cells from position `i` of `order` on.
+    rest: &'a [f64],
+    limit: f64,
+    /// Limit of the round before. Readings within it were checked and rejected already.
+    prev_limit: f64,
+    givens: [[u8; N]; N],
+    rows: [u16; N],
+    cols: [u16; N],
+    boxes: [u16; N],
+    best: Option<Best<N>>,
+    /// Some branch was cut by `limit`, so a higher threshold could find more.
+    cut: bool,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Default for NoisyGivens<N, BR, BC> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> NoisyGivens<N, BR, BC> {
+    pub fn new() -> Self {
+        Self::with_config(NoisyConfig::default())
+    }
+
+    pub fn with_config(config: NoisyConfig) -> Self {
+        Self {
+            config,
+            dfs: DfsBacktracking::with_config(DfsConfig {
+                propagation: Propagation::HiddenSingles,
+                ..DfsConfig::default()
+            }),
+            stats: NoisyStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> NoisyStats {
+        self.stats
+    }
+
+    /// `probs` holds one distribution per cell, row-major, each `N + 1` long: index `0` is
+    /// the probability that the cell is empty and index `val` that it holds `val`.
+    /// They need not sum to 1.
+    ///
+    /// Returns Err(SolveError::InvalidPuzzle) if the shape is wrong or a probability is
+    /// negative or not finite, Err(SolveError::Unsolvable) if no reading is accepted, or
+    /// Err(SolveError::GaveUp) after `max_checks` readings.
+    pub fn solve(&mut self, probs: &[Vec<f64>]) -> Result<Reading<N>, SolveError> {
+        self.stats = NoisyStats::default();
+        if probs.len() != N * N || probs.iter().any(|p| p.len() != N + 1) {
+            return Err(SolveError::InvalidPuzzle(
+                "need N + 1 probabilities for each cell",
+            ));
+        }
+        if probs.iter().flatten().any(|&p| !p.is_finite() || p < 0.0) {
+            return Err(SolveError::InvalidPuzzle(
+                "probability is negative or not finite",
+            ));
+        }
+
+        let options: Vec<Options> = probs
+            .iter()
+            .map(|p| {
+                let mut opts: Options = (0..=N as u8)
+                    .filter(|&v| p[v as usize] > 0.0)
+                    .map(|v| (v, -p[v as usize].ln()))
+                    .collect();
+                opts.sort_by(|a, b| a.1.total_cmp(&b.1));
+                opts
+            })
+            .collect();
+        if options.iter().any(Vec::is_empty) {
+            return Err(SolveError::Unsolvable);
+        }
+
+        // least certain cells first: the gap to their second choice is smallest
+        let regret = |o: &Options| o.get(1).map_or(f64::INFINITY, |second| second.1 - o[0].1);
+        let mut order: Vec<usize> = (0..N * N).collect();
+        order.sort_by(|&a, &b| regret(&options[a]).total_cmp(&regret(&options[b])));
+        let mut rest = vec![0.0; N * N + 1];
+        for i in (0..N * N).rev() {
+            rest[i] = rest[i + 1] + options[order[i]][0].1;
+        }
+
+        let base = rest[0];
+        // tied cells have no regret and would start the rounds at a useless step; a near
+        // tie would take many doublings to pass
+        let smallest = options
+            .iter()
+            .map(regret)
+            .filter(|&r| r > 0.0)
+            .fold(f64::INFINITY, f64::min);
+        let mut step = if smallest.is_finite() {
+            smallest.max(std::f64::consts::LN_2 / 16.0)
+        } else {
+            std::f64::consts::LN_2
+        };
+        let mut limit = base;
+        let mut prev_limit = f64::NEG_INFINITY;
+        loop {
+            self.stats.rounds += 1;
+            let mut round = Round {
+                order: &order,
+                options: &options,
+                rest: &rest,
+                // tolerance, so the threshold of a round does not lose readings to rounding
+                limit: limit + 1e-9 * (1.0 + base.abs()),
+                prev_limit,
+                givens: [[0; N]; N],
+                rows: [0; N],
+                cols: [0; N],
+                boxes: [0; N],
+                best: None,
+                cut: false,
+            };
+            self.branch(&mut round, 0, 0.0)?;
+
+            if let Some(Best {
+                cost,
+                givens,
+                solution,
+                unique,
+            }) = round.best
+            {
+                let overridden = (0..N * N)
+                    .filter(|&i| givens[i / N][i % N] != options[i][0].0)
+                    .map(|i| Override {
+                        row: i / N,
+                        col: i % N,
+                        read: options[i][0].0,
+                        chosen: givens[i / N][i % N],
+                    })
+                    .collect();
+                return Ok(Reading {
+                    givens,
+                    solution,
+                    overridden,
+                    log_prob: -cost,
+                    unique,
+                });
+            }
+            if !round.cut {
+                return Err(SolveError::Unsolvable);
+            }
+            prev_limit = round.limit;
+            limit = base + step;
+            step *= 2.0;
+        }
+    }
+
+    fn branch(&mut self, round: &mut Round<'_, N>, i: usize, cost: f64) -> Result<(), SolveError> {
+        let cost_at_least = cost + round.rest[i];
+        if cost_at_least > round.limit {
+            round.cut = true;
+            return Ok(());
+        }
+        if round.best.as_ref().is_some_and(|b| cost_at_least >= b.cost) {
+            return Ok(());
+        }
+        if i == round.order.len() {
+            if cost <= round.prev_limit {
+                return Ok(());
+            }
+            return self.check(round, cost);
+        }
+
+        let cell = round.order[i];
+        let (row, col) = (cell / N, cell % N);
+        let b = Sudoku::<N, BR, BC>::box_index(row, col);
+        for k in 0..round.options[cell].len() {
+            let (val, c) = round.options[cell][k];
+            if val == 0 {
+                self.branch(round, i + 1, cost + c)?;
+                continue;
+            }
+            let bit = 1u16 << (val - 1);
+            if (round.rows[row] | round.cols[col] | round.boxes[b]) & bit != 0 {
+                continue;
+            }
+            round.givens[row][col] = val;
+            round.rows[row] |= bit;
+            round.cols[col] |= bit;
+            round.boxes[b] |= bit;
+            let res = self.branch(round, i + 1, cost + c);
+            round.givens[row][col] = 0;
+            round.rows[row] &= !bit;
+            round.cols[col] &= !bit;
+            round.boxes[b] &= !bit;
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Solves a complete reading and keeps it if it is accepted and cheaper than the best.
+    fn check(&mut self, round: &mut Round<'_, N>, cost: f64) -> Result<(), SolveError> {
+        if self.stats.checks >= self.config.max_checks {
+            return Err(SolveError::GaveUp);
+        }
+        self.stats.checks += 1;
+
+        let mut s = Sudoku::<N, BR, BC>::new(round.givens);
+        let found = self.dfs.count_solutions(&mut s, 2)?;
+        let accepted = match found {
+            0 => false,
+            1 => true,
+            _ => !self.config.require_unique,
+        };
+        if accepted && round.best.as_ref().is_none_or(|b| cost < b.cost) {
+            round.best = Some(Best {
+                cost,
+                givens: round.givens,
+                solution: s.solution,
+                unique: found == 1,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{HARD, parse9};
+
+    /// A confident reading of `p`: 0.9 on the printed value, the rest spread evenly.
+    fn read9(p: &str) -> Vec<Vec<f64>> {
+        parse9(p)
+            .as_flattened()
+            .iter()
+            .map(|&v| {
+                (0..10)
+                    .map(|k| if k == v { 0.9 } else { 0.1 / 9.0 })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clean_reading_is_kept() {
+        let mut noisy = NoisyGivens::<9, 3, 3>::new();
+        let reading = noisy.solve(&read9(HARD)).unwrap();
+        assert!(reading.overridden.is_empty());
+        assert!(reading.unique);
+        assert_eq!(noisy.stats().checks, 1);
+        assert!(Sudoku::<9, 3, 3>::new(reading.solution).check());
+    }
+
+    #[test]
+    fn misread_given_is_overridden() {
+        // the 8 of the top-left cell read as a 3 that repeats in its box, with 8 the runner-up
+        let mut probs = read9(HARD);
+        probs[0] = vec![0.02; 10];
+        probs[0][3] = 0.6;
+        probs[0][8] = 0.3;
+        // an extra 1 in an empty cell, the same 1 given on its row
+        probs[6 * 9 + 3] = vec![0.01; 10];
+        probs[6 * 9 + 3][1] = 0.5;
+        probs[6 * 9 + 3][0] = 0.4;
+
+        let mut noisy = NoisyGivens::<9, 3, 3>::new();
+        let reading = noisy.solve(&probs).unwrap();
+        assert_eq!(
+            reading.overridden,
+            vec![
+                Override {
+                    row: 0,
+                    col: 0,
+                    read: 3,
+                    chosen: 8
+                },
+                Override {
+                    row: 6,
+                    col: 3,
+                    read: 1,
+                    chosen: 0
+                },
+            ]
+        );
+        assert!(reading.unique);
+        assert!(Sudoku::<9, 3, 3>::new(reading.solution).check());
+        assert!(noisy.solve(&probs[1..]).is_err());
+
+        // a 5 repeats nowhere, only solving shows the 8 is right
+        probs[0][5] = 0.6;
+        probs[0][3] = 0.02;
+        let reading = noisy.solve(&probs).unwrap();
+        assert_eq!(reading.givens[0][0], 8);
+        // the reading with the 5 is checked once, not again in each later round
+        assert_eq!((noisy.stats().rounds, noisy.stats().checks), (5, 2));
+
+        // a tie between two wrong digits does not shrink the step the rounds grow by
+        probs[0][3] = 0.45;
+        probs[0][5] = 0.45;
+        probs[0][8] = 0.05;
+        let reading = noisy.solve(&probs).unwrap();
+        assert_eq!(reading.givens[0][0], 8);
+        assert_eq!((noisy.stats().rounds, noisy.stats().checks), (6, 2));
+    }
+}
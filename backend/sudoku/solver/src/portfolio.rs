@@ -0,0 +1,131 @@
+use crate::{
+    solver::{CancelToken, Kind, SolveError, SolverEngine},
+    sudoku::Sudoku,
+};
+use std::{sync::Mutex, thread};
+
+/// Races several strategies on scoped threads and keeps the first answer.
+///
+/// An answer is a solution or a definite failure (`Unsolvable`, `InvalidPuzzle`);
+/// a member that gives up leaves the race to the others. Once a member answers the
+/// rest are cancelled. Member engines are kept between `solve` calls.
+pub struct Portfolio<const N: usize, const BR: usize, const BC: usize> {
+    members: Vec<SolverEngine<N, BR, BC>>,
+    cancel: CancelToken,
+    winner: Option<usize>,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Portfolio<N, BR, BC> {
+    pub fn new(kinds: Vec<Kind>) -> Self {
+        Self {
+            members: kinds.into_iter().map(SolverEngine::new).collect(),
+            cancel: CancelToken::new(),
+            winner: None,
+        }
+    }
+
+    pub fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    /// Index of the member that answered the last `solve`.
+    pub fn winner(&self) -> Option<usize> {
+        self.winner
+    }
+
+    pub fn solve(&mut self, s: &mut Sudoku<N, BR, BC>) -> Result<(), SolveError> {
+        self.winner = None;
+        // cancelled by the first answer, or from outside through `self.cancel`
+        let race = self.cancel.child();
+        let first = Mutex::new(None);
+        let (init, candidates) = (s.init, s.candidates);
+
+        thread::scope(|scope| {
+            for (i, member) in self.members.iter_mut().enumerate() {
+                member.set_cancel(race.clone());
+                let (race, first) = (&race, &first);
+                scope.spawn(move || {
+                    let mut local = Sudoku::<N, BR, BC>::with_candidates(init, candidates);
+                    let outcome = member.solve(&mut local).map(|_| local.solution);
+                    if matches!(outcome, Err(SolveError::GaveUp | SolveError::Cancelled)) {
+                        return;
+                    }
+                    let mut first = first.lock().expect("portfolio result poisoned");
+                    if first.is_none() {
+                        *first = Some((i, outcome));
+                        race.cancel();
+                    }
+                });
+            }
+        });
+
+        match first.into_inner().expect("portfolio result poisoned") {
+            Some((i, outcome)) => {
+                self.winner = Some(i);
+                s.solution = outcome?;
+                Ok(())
+            }
+            None if self.cancel.is_cancelled() => Err(SolveError::Cancelled),
+            None => Err(SolveError::GaveUp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        annealing::AnnealingConfig,
+        dfs::DfsConfig,
+        dfs::VarOrder,
+        sudoku::Sudoku9,
+        test_util::{HARD, parse9},
+    };
+
+    #[test]
+    fn portfolio_answers_with_a_member() {
+        // annealing gives up on this puzzle, so one of the exact solvers has to win
+        let kinds = vec![
+            Kind::Annealing(AnnealingConfig {
+                stall_limit: 1_000,
+                max_restarts: 2,
+                ..AnnealingConfig::default()
+            }),
+            Kind::Dfs,
+            Kind::DfsWith(DfsConfig {
+                var_order: VarOrder::DomWdeg,
+                ..DfsConfig::default()
+            }),
+            Kind::Sat,
+        ];
+        let mut engine = SolverEngine::<9, 3, 3>::new(Kind::Portfolio(kinds));
+        for _ in 0..3 {
+            let mut s = Sudoku9::new(parse9(HARD));
+            assert!(engine.solve(&mut s).is_ok());
+            assert!(s.check());
+            assert!(matches!(engine.winner(), Some(1..=3)));
+        }
+
+        let mut broken = parse9(HARD);
+        broken[0][1] = 8; // repeats the 8 in the same row
+        assert!(matches!(
+            engine.solve(&mut Sudoku9::new(broken)),
+            Err(SolveError::InvalidPuzzle(_))
+        ));
+        assert!(engine.winner().is_some());
+    }
+
+    #[test]
+    fn cancelled_portfolio_stops() {
+        let mut engine = SolverEngine::<9, 3, 3>::new(Kind::Portfolio(vec![Kind::Dfs, Kind::Sat]));
+        let token = CancelToken::new();
+        engine.set_cancel(token.clone());
+        token.cancel();
+        let mut s = Sudoku9::new(parse9(HARD));
+        assert_eq!(engine.solve(&mut s), Err(SolveError::Cancelled));
+        assert_eq!(engine.winner(), None);
+
+        let mut empty = SolverEngine::<9, 3, 3>::new(Kind::Portfolio(Vec::new()));
+        assert_eq!(empty.solve(&mut s), Err(SolveError::GaveUp));
+    }
+}
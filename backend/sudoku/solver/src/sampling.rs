@@ -0,0 +1,190 @@
+use crate::{dfs::DfsBacktracking, solver::SolveError, sudoku::Sudoku, transform::Transform};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerConfig {
+    /// Seed of the generator behind the choices. The same seed and puzzle always
+    /// produce the same sequence of samples.
+    pub seed: u64,
+    /// Solutions counted under each branch before counting stops. Branches with more
+    /// are weighted as if they had exactly this many, so a higher limit samples closer
+    /// to uniform, at the price of longer counts.
+    pub count_limit: u64,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            count_limit: 64,
+        }
+    }
+}
+
+/// Draws solutions of a puzzle at random, uniformly when the puzzle has at most
+/// `SamplerConfig::count_limit` of them.
+///
+/// Cells are fixed one at a time, fewest candidates first. Every candidate of the cell
+/// is weighted by the solutions it leaves, as counted by `DfsBacktracking` up to
+/// `SamplerConfig::count_limit`, and one is drawn in proportion. See `sample` for what
+/// happens above the limit.
+pub struct SolutionSampler<const N: usize, const BR: usize, const BC: usize> {
+    config: SamplerConfig,
+    rng: SmallRng,
+    dfs: DfsBacktracking<N, BR, BC>,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> Default for SolutionSampler<N, BR, BC> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> SolutionSampler<N, BR, BC> {
+    pub fn new() -> Self {
+        Self::with_config(SamplerConfig::default())
+    }
+
+    pub fn with_config(config: SamplerConfig) -> Self {
+        Self {
+            config,
+            rng: SmallRng::seed_from_u64(config.seed),
+            dfs: DfsBacktracking::new(),
+        }
+    }
+
+    /// A random solution of `s`, respecting its candidate masks. Successive calls draw
+    /// independently of each other.
+    ///
+    /// The draw is uniform only while the counts stay below `count_limit`. Branches
+    /// with more solutions all weigh the same, so once every branch reaches the limit
+    /// the choice is an even coin among them: a random depth-first descent, which
+    /// favours solutions in branches with few siblings. Puzzles with far more solutions
+    /// than the limit, e.g. near-empty grids, are sampled with that bias. Only a fully
+    /// empty grid with full candidate masks is corrected: its sample is moved by a
+    /// random `Transform`, which makes equivalent grids equally likely but still
+    /// leaves some classes of grids more likely than others.
+    /// Returns Err(SolveError::Unsolvable) if `s` has no solution and
+    /// Err(SolveError::InvalidPuzzle) for the inputs `DfsBacktracking` rejects.
+    pub fn sample(&mut self, s: &Sudoku<N, BR, BC>) -> Result<[[u8; N]; N], SolveError> {
+        let limit = self.config.count_limit.max(2);
+        let masks = s.candidates;
+        let mut grid = s.init;
+        let mut current = Sudoku::with_candidates(grid, masks);
+        let mut count = self.dfs.count_solutions(&mut current, limit)?;
+        if count == 0 {
+            return Err(SolveError::Unsolvable);
+        }
+
+        while count > 1 {
+            let (row, col, allowed) = Self::fewest_candidates(&grid, &masks);
+            let mut branches = Vec::with_capacity(N);
+            for val in (1..=N as u8).filter(|&v| allowed & (1 << (v - 1)) != 0) {
+                grid[row][col] = val;
+                let mut branch = Sudoku::with_candidates(grid, masks);
+                let solutions = self.dfs.count_solutions(&mut branch, limit)?;
+                if solutions > 0 {
+                    branches.push((val, solutions, branch));
+                }
+            }
+            let total: u64 = branches.iter().map(|b| b.1).sum();
+            let mut pick = self.rng.random_range(0..total);
+            let (val, solutions, branch) = branches
+                .into_iter()
+                .find(|b| {
+                    let hit = pick < b.1;
+                    pick = pick.saturating_sub(b.1);
+                    hit
+                })
+                .expect("pick is below the total");
+            grid[row][col] = val;
+            count = solutions;
+            current = branch;
+        }
+
+        let empty = s.init.as_flattened().iter().all(|&v| v == 0)
+            && masks
+                .as_flattened()
+                .iter()
+                .all(|&m| m == u16::MAX >> (16 - N));
+        Ok(if empty {
+            Transform::<N, BR, BC>::random(&mut self.rng).apply(&current.solution)
+        } else {
+            current.solution
+        })
+    }
+
+    /// The empty cell of `grid` with the fewest values its mask and peers allow, and
+    /// those values as a mask.
+    fn fewest_candidates(grid: &[[u8; N]; N], masks: &[[u16; N]; N]) -> (usize, usize, u16) {
+        let mut best = (0, 0, 0u16);
+        let mut fewest = u32::MAX;
+        for cell in (0..N * N).filter(|&c| grid[c / N][c % N] == 0) {
+            let (row, col) = (cell / N, cell % N);
+            let mut allowed = masks[row][col];
+            for &p in Sudoku::<N, BR, BC>::peers(cell) {
+                let v = grid[p as usize / N][p as usize % N];
+                if v != 0 {
+                    allowed &= !(1 << (v - 1));
+                }
+            }
+            if allowed.count_ones() < fewest {
+                fewest = allowed.count_ones();
+                best = (row, col, allowed);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sudoku::Sudoku9;
+    use std::collections::HashMap;
+
+    #[test]
+    fn samples_cover_the_solutions_evenly() {
+        // a 4x4 grid with one given has 72 solutions, few enough to count exactly
+        let mut init = [[0u8; 4]; 4];
+        init[0][0] = 1;
+        let puzzle = Sudoku::<4, 2, 2>::new(init);
+        let config = SamplerConfig {
+            seed: 7,
+            count_limit: 100,
+        };
+        let mut sampler = SolutionSampler::with_config(config);
+        let mut seen = HashMap::new();
+        for _ in 0..72 * 20 {
+            let grid = sampler.sample(&puzzle).unwrap();
+            let mut check = Sudoku::<4, 2, 2>::new(init);
+            check.solution = grid;
+            assert!(check.check() && grid[0][0] == 1);
+            *seen.entry(grid).or_insert(0) += 1;
+        }
+        assert_eq!(seen.len(), 72);
+        assert!(seen.values().all(|&n| (5..=40).contains(&n)), "{seen:?}");
+
+        // the same seed draws the same grids
+        let mut again = SolutionSampler::with_config(config);
+        let mut sampler = SolutionSampler::with_config(config);
+        assert_eq!(again.sample(&puzzle), sampler.sample(&puzzle));
+    }
+
+    #[test]
+    fn empty_grids_differ_and_dead_ends_fail() {
+        let mut sampler = SolutionSampler::<9, 3, 3>::new();
+        let empty = Sudoku9::new([[0; 9]; 9]);
+        let a = sampler.sample(&empty).unwrap();
+        let b = sampler.sample(&empty).unwrap();
+        assert_ne!(a, b);
+        let mut check = Sudoku9::new([[0; 9]; 9]);
+        check.solution = a;
+        assert!(check.check());
+
+        let mut masks = [[0x1ff; 9]; 9];
+        masks[0] = [0b1; 9];
+        let dead = Sudoku9::with_candidates([[0; 9]; 9], masks);
+        assert_eq!(sampler.sample(&dead), Err(SolveError::Unsolvable));
+    }
+}
@@ -0,0 +1,578 @@
+use crate::{
+    cnf,
+    dfs::luby,
+    solver::{CancelToken, SolveError},
+    sudoku::Sudoku,
+};
+
+/// Internal literal: `2 * var + 1` for the negation of the 0-based `var`.
+type Lit = u32;
+
+const UNDEF: i8 = 0;
+const TRUE: i8 = 1;
+const FALSE: i8 = -1;
+const NO_REASON: usize = usize::MAX;
+
+/// Conflicts in the first run, runs grow by the Luby sequence.
+const RESTART_UNIT: u64 = 100;
+const VAR_DECAY: f64 = 0.95;
+
+fn from_dimacs(lit: i32) -> Lit {
+    2 * (lit.unsigned_abs() - 1) + (lit < 0) as u32
+}
+
+fn var(lit: Lit) -> usize {
+    (lit >> 1) as usize
+}
+
+/// Counters of the last `Cdcl::solve` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SatStats {
+    pub decisions: u64,
+    pub conflicts: u64,
+    pub propagations: u64,
+    pub restarts: u32,
+}
+
+/// Binary max-heap of variables ordered by activity, with positions for decrease-key.
+#[derive(Default)]
+struct VarHeap {
+    heap: Vec<usize>,
+    /// Position of each variable in `heap`, `usize::MAX` when absent.
+    pos: Vec<usize>,
+}
+
+impl VarHeap {
+    fn contains(&self, v: usize) -> bool {
+        self.pos[v] != usize::MAX
+    }
+
+    fn insert(&mut self, v: usize, act: &[f64]) {
+        if !self.contains(v) {
+            self.pos[v] = self.heap.len();
+            self.heap.push(v);
+            self.up(self.pos[v], act);
+        }
+    }
+
+    fn pop(&mut self, act: &[f64]) -> Option<usize> {
+        let top = *self.heap.first()?;
+        let last = self.heap.pop()?;
+        self.pos[top] = usize::MAX;
+        if !self.heap.is_empty() {
+            self.heap[0] = last;
+            self.pos[last] = 0;
+            self.down(0, act);
+        }
+        Some(top)
+    }
+
+    fn up(&mut self, mut i: usize, act: &[f64]) {
+        let v = self.heap[i];
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if act[self.heap[parent]] >= act[v] {
+                break;
+            }
+            self.heap[i] = self.heap[parent];
+            self.pos[self.heap[i]] = i;
+            i = parent;
+        }
+        self.heap[i] = v;
+        self.pos[v] = i;
+    }
+
+    fn down(&mut self, mut i: usize, act: &[f64]) {
+        let v = self.heap[i];
+        loop {
+            let left = 2 * i + 1;
+            if left >= self.heap.len() {
+                break;
+            }
+            let right = left + 1;
+            let child = if right < self.heap.len() && act[self.heap[right]] > act[self.heap[left]] {
+                right
+            } else {
+                left
+            };
+            if act[self.heap[child]] <= act[v] {
+                break;
+            }
+            self.heap[i] = self.heap[child];
+            self.pos[self.heap[i]] = i;
+            i = child;
+        }
+        self.heap[i] = v;
+        self.pos[v] = i;
+    }
+}
+
+/// Conflict-driven clause learning SAT solver.
+///
+/// Two watched literals per clause, VSIDS branching with phase saving, first-UIP
+/// learning with local clause minimization and Luby restarts. Clauses may be added
+/// between `solve` calls; learned clauses are kept, so repeated calls get cheaper.
+pub struct Cdcl {
+    /// The first two literals of a clause are watched; a reason clause has the implied literal first.
+    clauses: Vec<Vec<Lit>>,
+    /// Clauses to visit when a literal becomes false.
+    watches: Vec<Vec<usize>>,
+    assigns: Vec<i8>,
+    level: Vec<u32>,
+    reason: Vec<usize>,
+    /// Last value of each variable, reused when it is decided again.
+    phase: Vec<bool>,
+    trail: Vec<Lit>,
+    /// Trail length at the start of each decision level.
+    trail_lim: Vec<usize>,
+    qhead: usize,
+    activity: Vec<f64>,
+    var_inc: f64,
+    order: VarHeap,
+    seen: Vec<bool>,
+    /// False once the clauses are known to be unsatisfiable.
+    ok: bool,
+    stats: SatStats,
+}
+
+impl Cdcl {
+    pub fn new(num_vars: usize) -> Self {
+        let mut solver = Self {
+            clauses: Vec::new(),
+            watches: vec![Vec::new(); 2 * num_vars],
+            assigns: vec![UNDEF; num_vars],
+            level: vec![0; num_vars],
+            reason: vec![NO_REASON; num_vars],
+            phase: vec![false; num_vars],
+            trail: Vec::with_capacity(num_vars),
+            trail_lim: Vec::new(),
+            qhead: 0,
+            activity: vec![0.0; num_vars],
+            var_inc: 1.0,
+            order: VarHeap {
+                heap: Vec::with_capacity(num_vars),
+                pos: vec![usize::MAX; num_vars],
+            },
+            seen: vec![false; num_vars],
+            ok: true,
+            stats: SatStats::default(),
+        };
+        for v in 0..num_vars {
+            solver.order.insert(v, &solver.activity);
+        }
+        solver
+    }
+
+    pub fn num_vars(&self) -> usize {
+        self.assigns.len()
+    }
+
+    pub fn stats(&self) -> SatStats {
+        self.stats
+    }
+
+    fn value(&self, lit: Lit) -> i8 {
+        let v = self.assigns[var(lit)];
+        if lit & 1 == 1 { -v } else { v }
+    }
+
+    fn decision_level(&self) -> u32 {
+        self.trail_lim.len() as u32
+    }
+
+    /// Adds a clause of DIMACS literals. Returns false if the clauses became unsatisfiable.
+    /// Any model of a previous `solve` call is dropped.
+    ///
+    /// Panics if a literal is 0 or refers to a variable above `num_vars`.
+    pub fn add_clause(&mut self, lits: &[i32]) -> bool {
+        self.backtrack(0);
+        if !self.ok {
+            return false;
+        }
+        let mut clause: Vec<Lit> = lits.iter().map(|&l| from_dimacs(l)).collect();
+        clause.sort_unstable();
+        clause.dedup();
+        if clause.windows(2).any(|w| w[0] ^ 1 == w[1])
+            || clause.iter().any(|&l| self.value(l) == TRUE)
+        {
+            return true; // tautology or already satisfied
+        }
+        clause.retain(|&l| self.value(l) == UNDEF);
+
+        match clause.len() {
+            0 => self.ok = false,
+            1 => {
+                self.enqueue(clause[0], NO_REASON);
+                self.ok = self.propagate().is_none();
+            }
+            _ => {
+                self.attach(clause);
+            }
+        }
+        self.ok
+    }
+
+    fn attach(&mut self, clause: Vec<Lit>) -> usize {
+        let id = self.clauses.len();
+        self.watches[clause[0] as usize].push(id);
+        self.watches[clause[1] as usize].push(id);
+        self.clauses.push(clause);
+        id
+    }
+
+    fn enqueue(&mut self, lit: Lit, reason: usize) {
+        let v = var(lit);
+        self.assigns[v] = if lit & 1 == 1 { FALSE } else { TRUE };
+        self.level[v] = self.decision_level();
+        self.reason[v] = reason;
+        self.trail.push(lit);
+    }
+
+    /// Unit propagation over the watches. Returns the conflicting clause, if any.
+    fn propagate(&mut self) -> Option<usize> {
+        while self.qhead < self.trail.len() {
+            let false_lit = self.trail[self.qhead] ^ 1;
+            self.qhead += 1;
+            self.stats.propagations += 1;
+
+            let mut ws = std::mem::take(&mut self.watches[false_lit as usize]);
+            let (mut i, mut j) = (0, 0);
+            let mut conflict = None;
+            'clauses: while i < ws.len() {
+                let id = ws[i];
+                i += 1;
+                let clause = &mut self.clauses[id];
+                if clause[0] == false_lit {
+                    clause.swap(0, 1);
+                }
+                let first = clause[0];
+                let value = |lit: Lit| {
+                    let v = self.assigns[var(lit)];
+                    if lit & 1 == 1 { -v } else { v }
+                };
+                if value(first) == TRUE {
+                    ws[j] = id;
+                    j += 1;
+                    continue;
+                }
+                for k in 2..clause.len() {
+                    if value(clause[k]) != FALSE {
+                        clause.swap(1, k);
+                        self.watches[clause[1] as usize].push(id);
+                        continue 'clauses;
+                    }
+                }
+
+                ws[j] = id;
+                j += 1;
+                if value(first) == FALSE {
+                    conflict = Some(id);
+                    while i < ws.len() {
+                        ws[j] = ws[i];
+                        j += 1;
+                        i += 1;
+                    }
+                } else {
+                    self.enqueue(first, id);
+                }
+            }
+            ws.truncate(j);
+            self.watches[false_lit as usize] = ws;
+            if conflict.is_some() {
+                self.qhead = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    fn bump(&mut self, v: usize) {
+        self.activity[v] += self.var_inc;
+        if self.activity[v] > 1e100 {
+            self.activity.iter_mut().for_each(|a| *a *= 1e-100);
+            self.var_inc *= 1e-100;
+        }
+        if self.order.contains(v) {
+            let i = self.order.pos[v];
+            self.order.up(i, &self.activity);
+        }
+    }
+
+    /// First-UIP conflict analysis. Returns the learned clause, asserting literal first
+    /// and a literal of the backjump level second, and the level to backjump to.
+    fn analyze(&mut self, mut conflict: usize) -> (Vec<Lit>, u32) {
+        let mut learnt: Vec<Lit> = vec![0];
+        let mut pending = 0;
+        let mut implied: Option<Lit> = None;
+        let mut idx = self.trail.len();
+
+        loop {
+            let skip = implied.is_some() as usize;
+            for k in skip..self.clauses[conflict].len() {
+                let q = self.clauses[conflict][k];
+                let v = var(q);
+                if !self.seen[v] && self.level[v] > 0 {
+                    self.seen[v] = true;
+                    self.bump(v);
+                    if self.level[v] >= self.decision_level() {
+                        pending += 1;
+                    } else {
+                        learnt.push(q);
+                    }
+                }
+            }
+            // the next literal of the current level to resolve on
+            loop {
+                idx -= 1;
+                if self.seen[var(self.trail[idx])] {
+                    break;
+                }
+            }
+            let p = self.trail[idx];
+            self.seen[var(p)] = false;
+            implied = Some(p);
+            pending -= 1;
+            if pending == 0 {
+                learnt[0] = p ^ 1;
+                break;
+            }
+            conflict = self.reason[var(p)];
+        }
+
+        // drop literals implied by the rest of the clause through their own reason
+        let kept: Vec<bool> = learnt
+            .iter()
+            .enumerate()
+            .map(|(i, &l)| {
+                let reason = self.reason[var(l)];
+                i == 0
+                    || reason == NO_REASON
+                    || self.clauses[reason][1..].iter().any(|&q| {
+                        let v = var(q);
+                        !self.seen[v] && self.level[v] > 0
+                    })
+            })
+            .collect();
+        for &l in &learnt[1..] {
+            self.seen[var(l)] = false;
+        }
+        let mut kept = kept.into_iter();
+        learnt.retain(|_| kept.next().unwrap_or(true));
+
+        let mut back = 0;
+        if learnt.len() > 1 {
+            let max = (1..learnt.len())
+                .max_by_key(|&i| self.level[var(learnt[i])])
+                .unwrap_or(1);
+            learnt.swap(1, max);
+            back = self.level[var(learnt[1])];
+        }
+        (learnt, back)
+    }
+
+    fn backtrack(&mut self, level: u32) {
+        if self.decision_level() <= level {
+            return;
+        }
+        let start = self.trail_lim[level as usize];
+        for i in (start..self.trail.len()).rev() {
+            let lit = self.trail[i];
+            let v = var(lit);
+            self.phase[v] = lit & 1 == 0;
+            self.assigns[v] = UNDEF;
+            self.reason[v] = NO_REASON;
+            self.order.insert(v, &self.activity);
+        }
+        self.trail.truncate(start);
+        self.trail_lim.truncate(level as usize);
+        self.qhead = start;
+    }
+
+    fn pick_branch(&mut self) -> Option<Lit> {
+        while let Some(v) = self.order.pop(&self.activity) {
+            if self.assigns[v] == UNDEF {
+                return Some(2 * v as u32 + !self.phase[v] as u32);
+            }
+        }
+        None
+    }
+
+    /// Searches for a model. Returns true if one was found, see `model_value`.
+    pub fn solve(&mut self) -> bool {
+        self.solve_until(|| false) == Some(true)
+    }
+
+    /// Like `solve`, but returns None as soon as `stop` returns true. `stop` is
+    /// called before every decision and after every conflict.
+    pub fn solve_until(&mut self, stop: impl Fn() -> bool) -> Option<bool> {
+        self.stats = SatStats::default();
+        self.backtrack(0);
+        if !self.ok {
+            return Some(false);
+        }
+        let mut budget = RESTART_UNIT * luby(1);
+        let mut run_conflicts = 0;
+
+        loop {
+            if stop() {
+                return None;
+            }
+            if let Some(conflict) = self.propagate() {
+                self.stats.conflicts += 1;
+                run_conflicts += 1;
+                if self.decision_level() == 0 {
+                    self.ok = false;
+                    return Some(false);
+                }
+                let (learnt, back) = self.analyze(conflict);
+                self.backtrack(back);
+                if learnt.len() == 1 {
+                    self.enqueue(learnt[0], NO_REASON);
+                } else {
+                    let lit = learnt[0];
+                    let id = self.attach(learnt);
+                    self.enqueue(lit, id);
+                }
+                self.var_inc /= VAR_DECAY;
+
+                if run_conflicts >= budget {
+                    self.backtrack(0);
+                    self.stats.restarts += 1;
+                    run_conflicts = 0;
+                    budget = RESTART_UNIT * luby(self.stats.restarts as u64 + 1);
+                }
+            } else {
+                let Some(lit) = self.pick_branch() else {
+                    return Some(true);
+                };
+                self.stats.decisions += 1;
+                self.trail_lim.push(self.trail.len());
+                self.enqueue(lit, NO_REASON);
+            }
+        }
+    }
+
+    /// Value of DIMACS variable `var` in the model found by the last successful `solve`.
+    pub fn model_value(&self, var: i32) -> bool {
+        self.value(from_dimacs(var)) == TRUE
+    }
+}
+
+/// Solves a puzzle by encoding it to CNF (see `Sudoku::to_cnf`) and running `Cdcl` on it.
+/// Independent of the search code, so it doubles as a cross-check for `DfsBacktracking`.
+#[derive(Default)]
+pub struct SatSolver<const N: usize, const BR: usize, const BC: usize> {
+    stats: SatStats,
+    cancel: CancelToken,
+}
+
+impl<const N: usize, const BR: usize, const BC: usize> SatSolver<N, BR, BC> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> SatStats {
+        self.stats
+    }
+
+    pub fn set_cancel(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    pub fn solve(&mut self, s: &mut Sudoku<N, BR, BC>) -> Result<(), SolveError> {
+        let formula = s.to_cnf()?;
+        let mut cdcl = Cdcl::new(formula.num_vars);
+        for clause in &formula.clauses {
+            cdcl.add_clause(clause);
+        }
+        let sat = cdcl.solve_until(|| self.cancel.is_cancelled());
+        self.stats = cdcl.stats();
+        match sat {
+            None => return Err(SolveError::Cancelled),
+            Some(false) => return Err(SolveError::Unsolvable),
+            Some(true) => {}
+        }
+
+        let grid = cnf::decode(N, |v| cdcl.model_value(v)).ok_or(SolveError::Unsolvable)?;
+        for (i, v) in grid.into_iter().enumerate() {
+            s.solution[i / N][i % N] = v;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sudoku::Sudoku9;
+
+    /// `pigeons` pigeons in `holes` holes, each hole holding at most one.
+    fn pigeonhole(pigeons: i32, holes: i32) -> Cdcl {
+        let x = |p: i32, h: i32| p * holes + h + 1;
+        let mut cdcl = Cdcl::new((pigeons * holes) as usize);
+        for p in 0..pigeons {
+            cdcl.add_clause(&(0..holes).map(|h| x(p, h)).collect::<Vec<_>>());
+        }
+        for h in 0..holes {
+            for p in 0..pigeons {
+                for q in p + 1..pigeons {
+                    cdcl.add_clause(&[-x(p, h), -x(q, h)]);
+                }
+            }
+        }
+        cdcl
+    }
+
+    #[test]
+    fn pigeonhole_satisfiability() {
+        assert!(!pigeonhole(6, 5).solve());
+        let mut cdcl = pigeonhole(5, 5);
+        assert!(cdcl.solve());
+        for h in 0..5 {
+            let in_hole = (0..5).filter(|p| cdcl.model_value(p * 5 + h + 1)).count();
+            assert_eq!(in_hole, 1);
+        }
+    }
+
+    #[test]
+    fn clauses_can_be_added_between_solves() {
+        let mut cdcl = Cdcl::new(2);
+        assert!(cdcl.add_clause(&[1, 2]));
+        assert!(cdcl.solve());
+        assert!(cdcl.add_clause(&[-1]));
+        assert!(cdcl.solve());
+        assert!(cdcl.model_value(2));
+        assert!(!cdcl.add_clause(&[-2]));
+        assert!(!cdcl.solve());
+    }
+
+    #[test]
+    fn sat_solver_reports_unsolvable_and_invalid() {
+        // 1 can go nowhere in the top-left box
+        let mut init = [[0u8; 9]; 9];
+        init[0][3] = 1;
+        init[4][1] = 1;
+        init[8][2] = 1;
+        let mut sat = SatSolver::new();
+        assert!(sat.solve(&mut Sudoku9::new(init)).is_ok());
+        for (r, c, v) in [
+            (1, 0, 2),
+            (2, 0, 3),
+            (1, 1, 4),
+            (2, 1, 5),
+            (1, 2, 6),
+            (2, 2, 7),
+        ] {
+            init[r][c] = v;
+        }
+        assert_eq!(
+            sat.solve(&mut Sudoku9::new(init)),
+            Err(SolveError::Unsolvable)
+        );
+        init[0][0] = 1;
+        assert!(matches!(
+            sat.solve(&mut Sudoku9::new(init)),
+            Err(SolveError::InvalidPuzzle(_))
+        ));
+    }
+}
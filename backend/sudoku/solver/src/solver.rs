@@ -1,22 +1,88 @@
-use crate::{dfs::DfsBacktracking, sudoku::Sudoku};
+use crate::{
+    annealing::{Annealing, AnnealingConfig},
+    dfs::{DfsBacktracking, DfsConfig},
+    portfolio::Portfolio,
+    sat::SatSolver,
+    sudoku::Sudoku,
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
 use thiserror::Error;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Kind {
     Dfs,
+    DfsWith(DfsConfig),
+    /// CNF encoding solved by the built-in CDCL solver.
+    Sat,
+    /// Simulated annealing over box permutations; may give up on hard or unsolvable puzzles.
+    Annealing(AnnealingConfig),
+    /// Runs every member on its own thread and takes the first answer, see `SolverEngine::winner`.
+    Portfolio(Vec<Kind>),
 }
 
 // Concrete strategies
 enum SolverEnum<const N: usize, const BR: usize, const BC: usize> {
-    Dfs(DfsBacktracking<N, BR, BC>),
+    Dfs(Box<DfsBacktracking<N, BR, BC>>),
+    Sat(SatSolver<N, BR, BC>),
+    Annealing(Annealing<N, BR, BC>),
+    Portfolio(Portfolio<N, BR, BC>),
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
 pub enum SolveError {
     #[error("puzzle is structurally invalid: {0}")]
     InvalidPuzzle(&'static str),
 
     #[error("puzzle has no solution")]
     Unsolvable,
+
+    #[error("solver gave up before finding a solution")]
+    GaveUp,
+
+    #[error("solver was cancelled")]
+    Cancelled,
+}
+
+/// Asks running solvers to stop with `SolveError::Cancelled`. Clones share the flag;
+/// a `child` token is also cancelled by its parent, but not the other way around.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    /// Own flag first, then the flags of the parents.
+    flags: Vec<Arc<AtomicBool>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self {
+            flags: vec![Arc::default()],
+        }
+    }
+
+    pub fn child(&self) -> Self {
+        let mut flags = Vec::with_capacity(self.flags.len() + 1);
+        flags.push(Arc::default());
+        flags.extend(self.flags.iter().cloned());
+        Self { flags }
+    }
+
+    pub fn cancel(&self) {
+        if let Some(flag) = self.flags.first() {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flags.iter().any(|f| f.load(Ordering::Relaxed))
+    }
 }
 
 // Abstract strategy
@@ -24,6 +90,18 @@ impl<const N: usize, const BR: usize, const BC: usize> SolverEnum<N, BR, BC> {
     fn solve(&mut self, s: &mut Sudoku<N, BR, BC>) -> Result<(), SolveError> {
         match self {
             SolverEnum::Dfs(a) => a.solve(s),
+            SolverEnum::Sat(a) => a.solve(s),
+            SolverEnum::Annealing(a) => a.solve(s),
+            SolverEnum::Portfolio(a) => a.solve(s),
+        }
+    }
+
+    fn set_cancel(&mut self, token: CancelToken) {
+        match self {
+            SolverEnum::Dfs(a) => a.set_cancel(token),
+            SolverEnum::Sat(a) => a.set_cancel(token),
+            SolverEnum::Annealing(a) => a.set_cancel(token),
+            SolverEnum::Portfolio(a) => a.set_cancel(token),
         }
     }
 }
@@ -37,7 +115,13 @@ impl<const N: usize, const BR: usize, const BC: usize> SolverEngine<N, BR, BC> {
     pub fn new(kind: Kind) -> Self {
         Self {
             alg: match kind {
-                Kind::Dfs => SolverEnum::Dfs(DfsBacktracking),
+                Kind::Dfs => SolverEnum::Dfs(Box::default()),
+                Kind::DfsWith(config) => {
+                    SolverEnum::Dfs(Box::new(DfsBacktracking::with_config(config)))
+                }
+                Kind::Sat => SolverEnum::Sat(SatSolver::new()),
+                Kind::Annealing(config) => SolverEnum::Annealing(Annealing::with_config(config)),
+                Kind::Portfolio(members) => SolverEnum::Portfolio(Portfolio::new(members)),
             },
         }
     }
@@ -45,11 +129,30 @@ impl<const N: usize, const BR: usize, const BC: usize> SolverEngine<N, BR, BC> {
     pub fn solve(&mut self, s: &mut Sudoku<N, BR, BC>) -> Result<(), SolveError> {
         self.alg.solve(s)
     }
+
+    /// Makes `solve` return Err(SolveError::Cancelled) soon after `token` is cancelled,
+    /// e.g. from another thread on a timeout.
+    pub fn set_cancel(&mut self, token: CancelToken) {
+        self.alg.set_cancel(token)
+    }
+
+    /// Position in the `Kind::Portfolio` list of the member that answered the last `solve`,
+    /// None for other kinds or if no member answered.
+    pub fn winner(&self) -> Option<usize> {
+        match &self.alg {
+            SolverEnum::Portfolio(a) => a.winner(),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::sudoku::Sudoku9;
+    use crate::{
+        dfs_fork::ForkingDfs,
+        sudoku::{Sudoku6, Sudoku9},
+        test_util::{HARD, parse9},
+    };
 
     use super::*;
 
@@ -107,4 +210,90 @@ mod tests {
         assert!(res.is_ok());
         assert!(sudoku.check())
     }
+
+    #[test]
+    fn dfs_matches_forking_dfs() {
+        // AI Escargot and Arto Inkala's 2012 puzzle
+        let puzzles = [
+            "1....7.9..3..2...8..96..5....53..9...1..8...26....4...3......1..4......7..7...3..",
+            HARD,
+        ];
+        let mut dfs = SolverEngine::new(Kind::Dfs);
+        let mut forking = ForkingDfs::new();
+        let mut sat = SolverEngine::new(Kind::Sat);
+        for p in puzzles {
+            let init = parse9(p);
+            let mut a = Sudoku9::new(init);
+            let mut b = Sudoku9::new(init);
+            let mut c = Sudoku9::new(init);
+            assert!(dfs.solve(&mut a).is_ok());
+            assert!(forking.solve(&mut b).is_ok());
+            assert!(sat.solve(&mut c).is_ok());
+            assert!(a.check());
+            assert_eq!(a.solution, b.solution);
+            assert_eq!(a.solution, c.solution);
+        }
+    }
+
+    #[test]
+    fn dfs_non_square_boxes_ok() {
+        let init_sudoku = [
+            [0, 0, 3, 0, 0, 0],
+            [0, 0, 0, 0, 2, 0],
+            [0, 0, 0, 5, 0, 0],
+            [0, 6, 0, 0, 0, 0],
+            [0, 0, 0, 0, 0, 4],
+            [1, 0, 0, 0, 0, 0],
+        ];
+        for kind in [
+            Kind::Dfs,
+            Kind::Sat,
+            Kind::Annealing(AnnealingConfig::default()),
+        ] {
+            let mut sudoku = Sudoku6::new(init_sudoku);
+            let res = SolverEngine::new(kind).solve(&mut sudoku);
+
+            assert!(res.is_ok());
+            assert!(sudoku.check());
+        }
+    }
+
+    #[test]
+    fn every_kind_honours_candidates() {
+        let mut masks = [[0b111111u16; 6]; 6];
+        masks[0][0] = 1 << 5; // only 6
+        masks[0][1] = 1 << 4; // only 5
+        masks[3][3] = 0b000110; // 2 or 3
+        masks[5][5] = 1; // only 1
+        let kinds = [
+            Kind::Dfs,
+            Kind::Sat,
+            Kind::Annealing(AnnealingConfig::default()),
+            Kind::Portfolio(vec![Kind::Dfs, Kind::Sat]),
+        ];
+        for kind in kinds {
+            let mut sudoku = Sudoku6::with_candidates([[0; 6]; 6], masks);
+            assert!(SolverEngine::new(kind.clone()).solve(&mut sudoku).is_ok());
+            assert!(sudoku.check());
+            let sol = sudoku.solution;
+            assert_eq!((sol[0][0], sol[0][1], sol[5][5]), (6, 5, 1), "{kind:?}");
+            assert!(matches!(sol[3][3], 2 | 3), "{kind:?}");
+        }
+
+        // two cells of the top row can only take a 6
+        masks[0][1] = 1 << 5;
+        masks[0][0] = 1 << 5;
+        for kind in [Kind::Dfs, Kind::Sat] {
+            let mut sudoku = Sudoku6::with_candidates([[0; 6]; 6], masks);
+            assert_eq!(
+                SolverEngine::new(kind).solve(&mut sudoku),
+                Err(SolveError::Unsolvable)
+            );
+        }
+        let mut sudoku = Sudoku6::with_candidates([[0; 6]; 6], masks);
+        assert_eq!(
+            ForkingDfs::new().solve(&mut sudoku),
+            Err(SolveError::Unsolvable)
+        );
+    }
 }
@@ -0,0 +1,517 @@
+use crate::{
+    cnf,
+    dynamic::{DynSudoku, is_specialized},
+    solver::SolveError,
+};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Puzzle(#[from] SolveError),
+
+    #[error("malformed record on line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+
+    #[error("source or tag holds a tab, a newline or, in a tag, a comma")]
+    BadText,
+
+    #[error("store has keys of version {found}, not {KEY_FORMAT}; reindex it")]
+    KeyFormat { found: u32 },
+}
+
+/// A puzzle kept in a `PuzzleStore`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PuzzleRecord {
+    /// Rows and columns of a box, as in `DynSudoku::box_shape`.
+    pub box_shape: (usize, usize),
+    /// Row-major, `0` for an empty cell.
+    pub givens: Vec<u8>,
+    pub solution: Option<Vec<u8>>,
+    /// Difficulty rating, higher is harder.
+    pub grade: Option<f32>,
+    /// Where the puzzle came from, e.g. a generator seed or an import file.
+    pub source: String,
+    pub tags: Vec<String>,
+}
+
+impl PuzzleRecord {
+    pub fn new(br: usize, bc: usize, givens: Vec<u8>) -> Self {
+        Self {
+            box_shape: (br, bc),
+            givens,
+            solution: None,
+            grade: None,
+            source: String::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.box_shape.0 * self.box_shape.1
+    }
+
+    /// Returns Err(StoreError::Puzzle) if the givens are invalid or the solution does
+    /// not solve them, Err(StoreError::BadText) if a text field would break the line format.
+    fn check(&self) -> Result<(), StoreError> {
+        let (br, bc) = self.box_shape;
+        let n = br * bc;
+        if n > 35 {
+            return Err(SolveError::InvalidPuzzle("grid is too large for the store").into());
+        }
+        cnf::check_givens(n, br, bc, &self.givens)?;
+        if let Some(solution) = &self.solution {
+            cnf::check_givens(n, br, bc, solution)?;
+            let agrees = solution
+                .iter()
+                .zip(&self.givens)
+                .all(|(&s, &g)| s != 0 && (g == 0 || g == s));
+            if !agrees {
+                return Err(SolveError::InvalidPuzzle("not a solution of the puzzle").into());
+            }
+        }
+        let bad = |t: &str| t.contains(['\t', '\n', '\r']);
+        if bad(&self.source) || self.tags.iter().any(|t| bad(t) || t.contains(',')) {
+            return Err(StoreError::BadText);
+        }
+        Ok(())
+    }
+}
+
+/// Which records `PuzzleStore::query` returns. Unset fields match everything; a grade
+/// bound leaves out records without a grade.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub size: Option<usize>,
+    pub min_grade: Option<f32>,
+    pub max_grade: Option<f32>,
+    pub tag: Option<String>,
+}
+
+impl Query {
+    fn matches(&self, r: &PuzzleRecord) -> bool {
+        self.size.is_none_or(|n| r.size() == n)
+            && self
+                .min_grade
+                .is_none_or(|min| r.grade.is_some_and(|g| g >= min))
+            && self
+                .max_grade
+                .is_none_or(|max| r.grade.is_some_and(|g| g <= max))
+            && self.tag.as_ref().is_none_or(|t| r.tags.contains(t))
+    }
+}
+
+fn encode_cells(cells: &[u8]) -> String {
+    cells
+        .iter()
+        .map(|&v| char::from_digit(v as u32, 36).expect("checked by PuzzleRecord::check"))
+        .collect()
+}
+
+fn decode_cells(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|ch| ch.to_digit(36).map(|v| v as u8))
+        .collect()
+}
+
+/// Version of the keys `canonical_key` makes, recorded in the first line of a store.
+/// Version 1 keys, from files without that line, were `transform::minlex` forms.
+pub const KEY_FORMAT: u32 = 2;
+
+/// Steps of the search behind `canonical_key`, a fraction of a second. Puzzles found so
+/// far need a few hundred thousand at most.
+const CANONICAL_NODES: u64 = 2_000_000;
+
+/// The key of a puzzle in a `PuzzleStore`: box shape and canonical form of the givens
+/// (see `transform::minlex_givens_first`), so transformations of a puzzle share it.
+///
+/// Shapes without a compiled `Sudoku` are keyed by their givens as they are, marked by
+/// `=` after the shape instead of `:`: only the same givens share such a key.
+/// Returns Err(SolveError::GaveUp) if the canonical form takes too long to find.
+pub fn canonical_key(br: usize, bc: usize, givens: &[u8]) -> Result<String, SolveError> {
+    let s = DynSudoku::new(br, bc, givens.to_vec())?;
+    if givens.iter().any(|&v| v as usize > s.size() || v >= 36) {
+        return Err(SolveError::InvalidPuzzle("given is out of range"));
+    }
+    if !is_specialized(s.size(), br, bc) {
+        return Ok(format!("{br}x{bc}={}", encode_cells(givens)));
+    }
+    let cells = s
+        .minlex_givens_first_within(CANONICAL_NODES)
+        .ok_or(SolveError::GaveUp)?;
+    Ok(format!("{br}x{bc}:{}", encode_cells(&cells)))
+}
+
+/// A record and the key it is filed under.
+type Keyed = (String, PuzzleRecord);
+
+/// Puzzles kept in an append-only text file and indexed in memory by `canonical_key`,
+/// so a puzzle and its transformations share one entry.
+///
+/// The first line is `#keys` and the `KEY_FORMAT` of the keys, tab-separated. Each
+/// further line holds one record as tab-separated fields: key, box shape `BRxBC`,
+/// givens, solution, grade, source and comma-separated tags. Cells are base-36 digits
+/// with `0` for empty, `-` marks a missing solution or grade. A later line with the
+/// same key replaces the earlier one, so updates are appends too. A last line without
+/// its newline, left by a crash during an append, is dropped on opening.
+///
+/// Keys are read as stored, so opening costs no canonical forms. `verify` recomputes
+/// them and `reindex` rewrites a store whose keys are stale or of another version.
+pub struct PuzzleStore {
+    file: File,
+    records: Vec<PuzzleRecord>,
+    index: HashMap<String, usize>,
+}
+
+impl PuzzleStore {
+    /// Opens the store at `path`, creating an empty one if there is no file, and loads
+    /// its records. Cuts off an unfinished last line.
+    /// Returns Err(StoreError::KeyFormat) for a store with keys of another version.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let (file, version, lines) = Self::read(path.as_ref())?;
+        if version != KEY_FORMAT {
+            return Err(StoreError::KeyFormat { found: version });
+        }
+        let mut store = Self {
+            file,
+            records: Vec::new(),
+            index: HashMap::new(),
+        };
+        for (key, record) in lines {
+            store.index_record(key, record);
+        }
+        Ok(store)
+    }
+
+    /// Rewrites the store at `path` with keys computed afresh, merging records that
+    /// turn out to share one as a later line would, and opens it. For stores with
+    /// stale keys or keys of an older `KEY_FORMAT`.
+    pub fn reindex(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let path = path.as_ref();
+        let (_, _, lines) = Self::read(path)?;
+        let mut fresh = path.as_os_str().to_owned();
+        fresh.push(".reindex");
+        let _ = std::fs::remove_file(&fresh);
+        {
+            let mut store = Self::open(&fresh)?;
+            for (_, record) in lines {
+                store.put(record)?;
+            }
+            store.file.sync_all()?;
+        }
+        std::fs::rename(&fresh, path)?;
+        Self::open(path)
+    }
+
+    /// The records whose stored key differs from the one `canonical_key` gives them,
+    /// e.g. after an edit by hand. `reindex` files them correctly.
+    pub fn verify(&self) -> Result<Vec<&PuzzleRecord>, SolveError> {
+        let mut stale = Vec::new();
+        for (key, &i) in &self.index {
+            let r = &self.records[i];
+            if canonical_key(r.box_shape.0, r.box_shape.1, &r.givens)? != *key {
+                stale.push(r);
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Opens or creates the file at `path`, cuts off an unfinished last line and parses
+    /// the rest. Returns the file, the key version and the records with their keys.
+    fn read(path: &Path) -> Result<(File, u32, Vec<Keyed>), StoreError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let complete = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        if complete < bytes.len() {
+            file.set_len(complete as u64)?;
+        }
+        if complete == 0 {
+            file.write_all(format!("#keys\t{KEY_FORMAT}\n").as_bytes())?;
+            return Ok((file, KEY_FORMAT, Vec::new()));
+        }
+        let text = std::str::from_utf8(&bytes[..complete])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut lines = text.lines().enumerate().peekable();
+        let version = match lines.peek().and_then(|(_, l)| l.strip_prefix("#keys\t")) {
+            Some(version) => {
+                lines.next();
+                version.parse().map_err(|_| StoreError::Malformed {
+                    line: 1,
+                    reason: "bad key version",
+                })?
+            }
+            None => 1,
+        };
+        let records = lines
+            .map(|(i, line)| {
+                Self::parse(line).map_err(|reason| StoreError::Malformed {
+                    line: i + 1,
+                    reason,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((file, version, records))
+    }
+
+    fn parse(line: &str) -> Result<Keyed, &'static str> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [key, shape, givens, solution, grade, source, tags] = fields[..] else {
+            return Err("expected 7 tab-separated fields");
+        };
+        let (br, bc) = shape
+            .split_once('x')
+            .and_then(|(br, bc)| Some((br.parse().ok()?, bc.parse().ok()?)))
+            .ok_or("bad box shape")?;
+        let cells = |text: &str| decode_cells(text).ok_or("bad cell");
+        let record = PuzzleRecord {
+            box_shape: (br, bc),
+            givens: cells(givens)?,
+            solution: match solution {
+                "-" => None,
+                text => Some(cells(text)?),
+            },
+            grade: match grade {
+                "-" => None,
+                text => Some(text.parse().map_err(|_| "bad grade")?),
+            },
+            source: source.to_string(),
+            tags: tags
+                .split(',')
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+        record.check().map_err(|_| "invalid record")?;
+        Ok((key.to_string(), record))
+    }
+
+    fn index_record(&mut self, key: String, record: PuzzleRecord) {
+        match self.index.get(&key) {
+            Some(&i) => self.records[i] = record,
+            None => {
+                self.index.insert(key, self.records.len());
+                self.records.push(record);
+            }
+        }
+    }
+
+    fn append(&mut self, key: String, record: PuzzleRecord) -> Result<(), StoreError> {
+        let line = format!(
+            "{key}\t{}x{}\t{}\t{}\t{}\t{}\t{}\n",
+            record.box_shape.0,
+            record.box_shape.1,
+            encode_cells(&record.givens),
+            record.solution.as_deref().map_or("-".into(), encode_cells),
+            record.grade.map_or("-".into(), |g| g.to_string()),
+            record.source,
+            record.tags.join(","),
+        );
+        self.file.write_all(line.as_bytes())?;
+        self.index_record(key, record);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Adds `record` unless a transformation of its givens is stored already.
+    /// Returns true if it was added.
+    pub fn insert(&mut self, record: PuzzleRecord) -> Result<bool, StoreError> {
+        record.check()?;
+        let key = canonical_key(record.box_shape.0, record.box_shape.1, &record.givens)?;
+        if self.index.contains_key(&key) {
+            return Ok(false);
+        }
+        self.append(key, record)?;
+        Ok(true)
+    }
+
+    /// Adds `record`, replacing the stored transformation of its givens if there is one.
+    pub fn put(&mut self, record: PuzzleRecord) -> Result<(), StoreError> {
+        record.check()?;
+        let key = canonical_key(record.box_shape.0, record.box_shape.1, &record.givens)?;
+        self.append(key, record)
+    }
+
+    /// The stored record for the givens or a transformation of them. Its cells are as
+    /// they were stored, not necessarily as given here.
+    pub fn get(
+        &self,
+        br: usize,
+        bc: usize,
+        givens: &[u8],
+    ) -> Result<Option<&PuzzleRecord>, SolveError> {
+        let key = canonical_key(br, bc, givens)?;
+        Ok(self.index.get(&key).map(|&i| &self.records[i]))
+    }
+
+    /// All records, in the order they were first added.
+    pub fn iter(&self) -> impl Iterator<Item = &PuzzleRecord> {
+        self.records.iter()
+    }
+
+    pub fn query<'a>(&'a self, query: &'a Query) -> impl Iterator<Item = &'a PuzzleRecord> {
+        self.records.iter().filter(|r| query.matches(r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{HARD, parse9},
+        transform::Transform,
+    };
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    #[test]
+    fn store_dedups_and_survives_reopening() {
+        let path = std::env::temp_dir().join(format!("solver-store-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let hard = parse9(HARD);
+        let moved = Transform::<9, 3, 3>::random(&mut SmallRng::seed_from_u64(2)).apply(&hard);
+        let mut small = vec![0u8; 36];
+        small[2] = 3;
+        small[10] = 2;
+        {
+            let mut store = PuzzleStore::open(&path).unwrap();
+            let mut record = PuzzleRecord::new(3, 3, hard.as_flattened().to_vec());
+            record.grade = Some(10.5);
+            record.source = "Inkala 2012".into();
+            record.tags = vec!["famous".into()];
+            assert!(store.insert(record.clone()).unwrap());
+            // the same puzzle relabeled and shuffled is a duplicate
+            assert!(
+                !store
+                    .insert(PuzzleRecord::new(3, 3, moved.as_flattened().to_vec()))
+                    .unwrap()
+            );
+            let mut six = PuzzleRecord::new(2, 3, small.clone());
+            six.grade = Some(1.5);
+            assert!(store.insert(six).unwrap());
+            record.tags.push("hard".into());
+            store.put(record).unwrap();
+
+            let mut bad = PuzzleRecord::new(3, 3, hard.as_flattened().to_vec());
+            bad.tags = vec!["a,b".into()];
+            assert!(matches!(store.insert(bad), Err(StoreError::BadText)));
+        }
+
+        let store = PuzzleStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        let found = store.get(3, 3, moved.as_flattened()).unwrap().unwrap();
+        assert_eq!(found.givens, hard.as_flattened());
+        assert_eq!(found.tags, ["famous", "hard"]);
+        assert_eq!(found.grade, Some(10.5));
+
+        let hard_ones = Query {
+            min_grade: Some(5.0),
+            ..Query::default()
+        };
+        assert_eq!(store.query(&hard_ones).count(), 1);
+        let sixes = Query {
+            size: Some(6),
+            ..Query::default()
+        };
+        assert_eq!(store.query(&sixes).next().unwrap().givens, small);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn store_drops_a_torn_line_and_reindexes() {
+        let path = std::env::temp_dir().join(format!("solver-torn-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut store = PuzzleStore::open(&path).unwrap();
+            let hard = PuzzleRecord::new(3, 3, parse9(HARD).as_flattened().to_vec());
+            assert!(store.insert(hard).unwrap());
+            let mut four = PuzzleRecord::new(2, 2, vec![0; 16]);
+            four.givens[5] = 3;
+            assert!(store.insert(four).unwrap());
+        }
+
+        // a crash in the middle of an append
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with(&format!("#keys\t{KEY_FORMAT}\n")));
+        let first = text.lines().nth(1).unwrap();
+        std::fs::write(&path, format!("{text}{}", &first[..30])).unwrap();
+        {
+            let mut store = PuzzleStore::open(&path).unwrap();
+            assert_eq!(store.len(), 2);
+            assert!(store.verify().unwrap().is_empty());
+            let mut six = PuzzleRecord::new(2, 3, vec![0; 36]);
+            six.givens[0] = 1;
+            assert!(store.insert(six).unwrap());
+        }
+        assert_eq!(PuzzleStore::open(&path).unwrap().len(), 3);
+
+        // a line filed under a key of its own, as an older version might have made
+        let text = std::fs::read_to_string(&path).unwrap();
+        let stale = first.replacen(first.split('\t').next().unwrap(), "3x3:stale", 1);
+        std::fs::write(&path, format!("{text}{stale}\n")).unwrap();
+        let store = PuzzleStore::open(&path).unwrap();
+        assert_eq!(store.len(), 4);
+        let found = store.verify().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].givens, parse9(HARD).as_flattened());
+        assert_eq!(PuzzleStore::reindex(&path).unwrap().len(), 3);
+
+        // a file without the version line holds version 1 keys
+        let text = std::fs::read_to_string(&path).unwrap();
+        let records = text.split_once('\n').unwrap().1;
+        std::fs::write(&path, records).unwrap();
+        assert!(matches!(
+            PuzzleStore::open(&path),
+            Err(StoreError::KeyFormat { found: 1 })
+        ));
+        let store = PuzzleStore::reindex(&path).unwrap();
+        assert_eq!(store.len(), 3);
+        assert!(store.verify().unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sparse_puzzles_share_a_key() {
+        // few givens in different rows and columns, where minlex ties at every cell
+        let mut sparse = [[0u8; 16]; 16];
+        for (i, cell) in [3, 21, 40, 58, 77, 95, 110, 130, 149, 167]
+            .into_iter()
+            .enumerate()
+        {
+            sparse[cell / 16][cell % 16] = i as u8 + 1;
+        }
+        let key = canonical_key(4, 4, sparse.as_flattened()).unwrap();
+        assert!(key.starts_with("4x4:"));
+        let mut rng = SmallRng::seed_from_u64(4);
+        for _ in 0..3 {
+            let moved = Transform::<16, 4, 4>::random(&mut rng).apply(&sparse);
+            assert_eq!(canonical_key(4, 4, moved.as_flattened()).unwrap(), key);
+        }
+
+        // shapes without a canonical form are keyed as given, and marked so
+        let mut five = vec![0u8; 25 * 25];
+        five[7] = 3;
+        assert!(canonical_key(5, 5, &five).unwrap().starts_with("5x5="));
+    }
+}
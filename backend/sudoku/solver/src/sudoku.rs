@@ -1,79 +1,182 @@
+use crate::solver::SolveError;
 use std::fmt;
 
 pub struct Sudoku<const N: usize, const BR: usize, const BC: usize> {
     pub(crate) init: [[u8; N]; N],
     pub(crate) solution: [[u8; N]; N],
+    /// Values still allowed in each cell, bit `val - 1` for `val`. All of them unless
+    /// built `with_candidates`.
+    pub(crate) candidates: [[u16; N]; N],
 }
 
 pub type Sudoku9 = Sudoku<9, 3, 3>;
 pub type Sudoku6 = Sudoku<6, 2, 3>;
 
+/// Cells of the largest grid, 16x16.
+pub const MAX_CELLS: usize = 256;
+/// Most peers a cell can have: 15 in its row, 15 in its column and 9 more in a 4x4 box.
+pub const MAX_PEERS: usize = 39;
+
+// cell c of the tables is row * n + col; rows past n * n cells and entries past the
+// peer count stay 0
+const fn peer_table(n: usize, br: usize, bc: usize) -> [[u8; MAX_PEERS]; MAX_CELLS] {
+    let mut table = [[0u8; MAX_PEERS]; MAX_CELLS];
+    let mut cell = 0;
+    while cell < n * n {
+        let (r, c) = (cell / n, cell % n);
+        let mut k = 0;
+        let mut i = 0;
+        while i < n {
+            if i != r {
+                table[cell][k] = (i * n + c) as u8;
+                k += 1;
+            }
+            if i != c {
+                table[cell][k] = (r * n + i) as u8;
+                k += 1;
+            }
+            i += 1;
+        }
+        let (r0, c0) = (r / br * br, c / bc * bc);
+        let mut rr = r0;
+        while rr < r0 + br {
+            let mut cc = c0;
+            while cc < c0 + bc {
+                if rr != r && cc != c {
+                    table[cell][k] = (rr * n + cc) as u8;
+                    k += 1;
+                }
+                cc += 1;
+            }
+            rr += 1;
+        }
+        cell += 1;
+    }
+    table
+}
+
+const fn unit_table(n: usize, br: usize, bc: usize) -> [[u8; 16]; 48] {
+    let mut table = [[0u8; 16]; 48];
+    let mut i = 0;
+    while i < n {
+        let (r0, c0) = (i / br * br, i % br * bc);
+        let mut k = 0;
+        while k < n {
+            table[i][k] = (i * n + k) as u8;
+            table[n + i][k] = (k * n + i) as u8;
+            table[2 * n + i][k] = ((r0 + k / bc) * n + c0 + k % bc) as u8;
+            k += 1;
+        }
+        i += 1;
+    }
+    table
+}
+
 impl<const N: usize, const BR: usize, const BC: usize> Sudoku<N, BR, BC> {
+    const FULL_MASK: u16 = u16::MAX >> (16 - N);
+
     pub fn new(init: [[u8; N]; N]) -> Self {
         Sudoku {
             init,
             solution: init,
+            candidates: [[Self::FULL_MASK; N]; N],
         }
     }
 
-    #[inline]
-    pub fn box_index(r: usize, c: usize) -> usize {
-        (r / BR) * BC + (c / BC)
+    /// A puzzle whose empty cells may only take the values of their `candidates` mask
+    /// (bit `val - 1` for `val`), e.g. a pencilmark state. Masks of filled cells must
+    /// hold the given. Solvers answer `SolveError::Unsolvable` if no solution respects them.
+    pub fn with_candidates(init: [[u8; N]; N], candidates: [[u16; N]; N]) -> Self {
+        let mut s = Self::new(init);
+        for (row, masks) in s.candidates.iter_mut().zip(candidates) {
+            for (m, c) in row.iter_mut().zip(masks) {
+                *m = c & Self::FULL_MASK;
+            }
+        }
+        s
     }
 
+    /// Peers of every cell, the cells sharing its row, column or box.
+    pub const PEER_COUNT: usize = 2 * (N - 1) + (BR - 1) * (BC - 1);
+    const PEERS: &'static [[u8; MAX_PEERS]; MAX_CELLS] = &peer_table(N, BR, BC);
+    const UNITS: &'static [[u8; 16]; 48] = &unit_table(N, BR, BC);
+
+    /// Peers of `cell` (`row * N + col`) as cell indices: column and row peers
+    /// interleaved by index, then the rest of the box. Built at compile time.
     #[inline]
-    pub fn box_coord(i: usize, j: usize) -> (usize, usize) {
-        (i / BR * BC, j / BC * BR)
+    pub fn peers(cell: usize) -> &'static [u8] {
+        &Self::PEERS[cell][..Self::PEER_COUNT]
     }
 
-    pub fn check(&self) -> bool {
-        let full: u32 = (1u32 << N) - 1;
+    /// Cells of `unit`: units 0..N are rows, N..2N columns and 2N..3N boxes in
+    /// `box_index` order, each listed row-major. Built at compile time.
+    #[inline]
+    pub fn unit(unit: usize) -> &'static [u8] {
+        &Self::UNITS[unit][..N]
+    }
 
-        for i in 0..N {
-            let (mut row, mut col) = (0u32, 0u32);
-            for j in 0..N {
-                let rv = self.solution[i][j];
-                let cv = self.solution[j][i];
-                if rv == 0 || cv == 0 {
-                    return false;
-                }
+    /// Row, column and box unit of `cell`, numbered as in `unit`.
+    #[inline]
+    pub fn units_of(cell: usize) -> [usize; 3] {
+        let (r, c) = (cell / N, cell % N);
+        [r, N + c, 2 * N + Self::box_index(r, c)]
+    }
 
-                let rb = 1u32 << (rv - 1);
-                let cb = 1u32 << (cv - 1);
+    pub fn init(&self) -> &[[u8; N]; N] {
+        &self.init
+    }
 
-                if (row & rb) != 0 || (col & cb) != 0 {
-                    return false;
-                }
+    /// The grid filled by the last successful solve, or the givens before that.
+    pub fn solution(&self) -> &[[u8; N]; N] {
+        &self.solution
+    }
 
-                row |= rb;
-                col |= cb;
-            }
+    pub fn candidates(&self) -> &[[u16; N]; N] {
+        &self.candidates
+    }
 
-            if row != full || col != full {
-                return false;
-            }
+    /// Returns Err(SolveError::InvalidPuzzle) if a given is missing from its own candidate mask.
+    pub(crate) fn check_candidates(&self) -> Result<(), SolveError> {
+        let excluded = self
+            .init
+            .as_flattened()
+            .iter()
+            .zip(self.candidates.as_flattened())
+            .any(|(&v, &m)| v > 0 && v as usize <= N && m & (1 << (v - 1)) == 0);
+        if excluded {
+            return Err(SolveError::InvalidPuzzle(
+                "given is not among its candidates",
+            ));
         }
+        Ok(())
+    }
 
-        for br in (0..N).step_by(BR) {
-            for bc in (0..N).step_by(BC) {
-                let mut boxm = 0u32;
-                for dr in 0..BR {
-                    for dc in 0..BC {
-                        let v = self.solution[br + dr][bc + dc];
-                        let b = 1u32 << (v - 1);
-                        if (boxm & b) != 0 {
-                            return false;
-                        }
-                        boxm |= b;
-                    }
-                }
-                if boxm != full {
+    // boxes are BR rows high and BC columns wide, so a band holds N / BC = BR boxes
+    #[inline]
+    pub fn box_index(r: usize, c: usize) -> usize {
+        (r / BR) * BR + (c / BC)
+    }
+
+    #[inline]
+    pub fn box_coord(i: usize, j: usize) -> (usize, usize) {
+        (i / BR * BR, j / BC * BC)
+    }
+
+    pub fn check(&self) -> bool {
+        let full: u32 = (1u32 << N) - 1;
+        let cells = self.solution.as_flattened();
+
+        (0..3 * N).all(|unit| {
+            let mut seen = 0u32;
+            for &cell in Self::unit(unit) {
+                let v = cells[cell as usize];
+                if v == 0 || v as usize > N || seen & (1 << (v - 1)) != 0 {
                     return false;
                 }
+                seen |= 1 << (v - 1);
             }
-        }
-
-        true
+            seen == full
+        })
     }
 }
 
@@ -88,3 +191,43 @@ impl<const N: usize, const BR: usize, const BC: usize> fmt::Display for Sudoku<N
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tables_agree<const N: usize, const BR: usize, const BC: usize>() {
+        for cell in 0..N * N {
+            let units = Sudoku::<N, BR, BC>::units_of(cell);
+            for unit in units {
+                assert!(Sudoku::<N, BR, BC>::unit(unit).contains(&(cell as u8)));
+            }
+            // peers are exactly the other cells of the cell's three units
+            let mut expected: Vec<u8> = units
+                .iter()
+                .flat_map(|&u| Sudoku::<N, BR, BC>::unit(u).iter().copied())
+                .filter(|&p| p as usize != cell)
+                .collect();
+            expected.sort_unstable();
+            expected.dedup();
+            let mut peers = Sudoku::<N, BR, BC>::peers(cell).to_vec();
+            peers.sort_unstable();
+            assert_eq!(peers, expected);
+            assert_eq!(peers.len(), Sudoku::<N, BR, BC>::PEER_COUNT);
+        }
+    }
+
+    #[test]
+    fn peer_and_unit_tables() {
+        tables_agree::<4, 2, 2>();
+        tables_agree::<6, 2, 3>();
+        tables_agree::<9, 3, 3>();
+        tables_agree::<12, 4, 3>();
+        tables_agree::<16, 4, 4>();
+
+        assert_eq!(Sudoku9::PEER_COUNT, 20);
+        // 6x6 boxes are 2 rows high and 3 columns wide
+        assert_eq!(Sudoku6::unit(12 + 1), &[3, 4, 5, 9, 10, 11]);
+        assert_eq!(Sudoku6::units_of(4 * 6 + 4), [4, 10, 12 + 5]);
+    }
+}
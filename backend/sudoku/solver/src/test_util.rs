@@ -0,0 +1,14 @@
+//! Puzzles and helpers shared by the unit tests.
+
+// Arto Inkala's 2012 puzzle
+pub(crate) const HARD: &str =
+    "8..........36......7..9.2...5...7.......457.....1...3...1....68..85...1..9....4..";
+
+/// A 9x9 grid from 81 characters, `.` for an empty cell.
+pub(crate) fn parse9(p: &str) -> [[u8; 9]; 9] {
+    let mut init = [[0u8; 9]; 9];
+    for (i, ch) in p.bytes().enumerate() {
+        init[i / 9][i % 9] = if ch == b'.' { 0 } else { ch - b'0' };
+    }
+    init
+}
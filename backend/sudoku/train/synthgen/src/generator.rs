@@ -205,7 +205,7 @@ impl<const N: usize, const BR: usize, const BC: usize> DatasetItemGenerator<'_,
     }
 
     fn get_line_thickness(&self, i: usize, block: usize) -> u32 {
-        if i == 0 || i == N || i % block == 0 {
+        if i == 0 || i == N || i.is_multiple_of(block) {
             self.config.line_thick
         } else if self.config.do_cell_grid {
             self.config.line_thin
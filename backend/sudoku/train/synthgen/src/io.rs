@@ -5,7 +5,6 @@ use crate::{
     render::Highlight,
 };
 use image::RgbaImage;
-use serde_json;
 use std::{
     fs::File,
     io::{BufWriter, Error, Write},